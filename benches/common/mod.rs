@@ -0,0 +1,24 @@
+use idwteg::color::RGBColor;
+use idwteg::image::PPMImage;
+
+// Noisy gradient, a synthetic cover so that no image files are needed. Secrets are covers of a
+// different size.
+pub fn cover(size: usize) -> PPMImage {
+    let mut seed = 1_u32;
+    let mut img = PPMImage::new();
+    img.img_type = String::from("P6");
+    img.width = size;
+    img.height = size;
+    img.max_val = 255;
+    for i in 0..size * size {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let noise = (seed >> 16) as i32 % 32;
+        let (x, y) = ((i % size) as i32, (i / size) as i32);
+        img.data.push(RGBColor::new(
+            (x + noise) % 256,
+            (y + noise) % 256,
+            (x + y) % 256,
+        ));
+    }
+    return img;
+}
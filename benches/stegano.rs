@@ -0,0 +1,100 @@
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+mod common;
+
+use idwteg::algorithm::StegoAlgorithm;
+use idwteg::color::RGBColor;
+use idwteg::progress::Monitor;
+use idwteg::stegano::{Block, ChannelSelection, DWTImage, DWTStegano};
+
+// Matching and block replacement are quadratic in the cover size, so the covers are much smaller
+// than for the transforms. Secrets have a quarter of the cover side. Throughput is in pixels of
+// the cover (Melem/s is megapixels per second).
+const SIZES: [usize; 3] = [64, 128, 256];
+
+fn blocks(size: usize) -> [Vec<Block<RGBColor<i32>>>; 5] {
+    let cover = DWTImage::from_ppm(&common::cover(size));
+    let secret = DWTImage::from_ppm(&common::cover(size / 4));
+    let (w, h) = (cover.orig_width, cover.orig_height);
+    return [
+        DWTImage::blocking_extract_one(&cover.ll, w, h),
+        DWTImage::blocking_extract_one(&cover.lh, w, h),
+        DWTImage::blocking_extract_one(&cover.hl, w, h),
+        DWTImage::blocking_extract_one(&cover.hh, w, h),
+        DWTImage::blocking_extract_one(&secret.ll, secret.orig_width, secret.orig_height),
+    ];
+}
+
+fn matching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matching");
+    group.sample_size(10);
+    let weights = ChannelSelection::default().weights;
+    let monitor = Monitor::default();
+    for size in SIZES {
+        let [ia, _, _, _, sa] = blocks(size);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| DWTImage::matching(black_box(&sa), black_box(&ia), &weights, &monitor))
+        });
+    }
+    group.finish();
+}
+
+fn block_replacement(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_replacement");
+    group.sample_size(10);
+    let channels = ChannelSelection::default();
+    let monitor = Monitor::default();
+    for size in SIZES {
+        let [ia, ih, iv, id, sa] = blocks(size);
+        let key = DWTImage::matching(&sa, &ia, &channels.weights, &monitor).unwrap();
+        let bd = DWTImage::block_differences_computation(&sa, &ia, &key, &monitor).unwrap();
+        group.throughput(Throughput::Elements((size * size) as u64));
+        // the detail sub-bands are overwritten, every iteration starts from fresh copies
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_batched(
+                || (bd.clone(), ih.clone(), iv.clone(), id.clone()),
+                |(mut bd, mut ih, mut iv, mut id)| {
+                    DWTImage::block_replacement(
+                        &mut bd, &mut ih, &mut iv, &mut id, &channels, &monitor,
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// `hide_image` and `extract_message_from_image` without the files
+fn end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dwt_stegano");
+    group.sample_size(10);
+    let algorithm = DWTStegano::default();
+    for size in SIZES {
+        let (cover, secret) = (common::cover(size), common::cover(size / 4));
+        let (stego, key) = algorithm.embed(&cover, &secret).unwrap();
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_function(BenchmarkId::new("hide", size), |b| {
+            b.iter(|| {
+                algorithm
+                    .embed(black_box(&cover), black_box(&secret))
+                    .unwrap()
+            })
+        });
+        group.bench_function(BenchmarkId::new("extract", size), |b| {
+            b.iter(|| {
+                algorithm
+                    .extract(black_box(&stego), black_box(&key))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, matching, block_replacement, end_to_end);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+mod common;
+
+use idwteg::color::RGBColor;
+use idwteg::image::PPMImage;
+use idwteg::planar::{PlanarDWT, Planes, Wavelet};
+use idwteg::stegano::{DWTImage, Quantization};
+
+// Throughput is in pixels of the cover, which criterion reports as Melem/s, i.e. megapixels per
+// second
+const SIZES: [usize; 3] = [256, 1024, 2048];
+
+// `DWTImage` through planes: the conversions to and from planes around the planar transform
+fn planar_forward(img: &PPMImage) -> DWTImage {
+    return PlanarDWT::forward(&Planes::from_image(img)).to_dwt_image();
+}
+
+fn planar_inverse(dwt: &DWTImage) -> Vec<RGBColor<f64>> {
+    return PlanarDWT::from_dwt_image(dwt).inverse().to_pixels();
+}
+
+fn forward(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forward");
+    for size in SIZES {
+        let planes = Planes::from_image(&common::cover(size));
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::new("haar", size), &planes, |b, planes| {
+            b.iter(|| PlanarDWT::forward(black_box(planes)))
+        });
+        group.bench_with_input(BenchmarkId::new("legall53", size), &planes, |b, planes| {
+            b.iter(|| PlanarDWT::forward_in(black_box(planes), Wavelet::LeGall53))
+        });
+    }
+    group.finish();
+}
+
+fn inverse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inverse");
+    for size in SIZES {
+        let planes = Planes::from_image(&common::cover(size));
+        let (haar, legall) = (
+            PlanarDWT::forward(&planes),
+            PlanarDWT::forward_in(&planes, Wavelet::LeGall53),
+        );
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::new("haar", size), &haar, |b, dwt| {
+            b.iter(|| black_box(dwt).inverse())
+        });
+        group.bench_with_input(BenchmarkId::new("legall53", size), &legall, |b, dwt| {
+            b.iter(|| black_box(dwt).inverse())
+        });
+    }
+    group.finish();
+}
+
+// The pixel by pixel transform of `DWTImage` against the same through planes
+fn dwt_image(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dwt_image");
+    for size in SIZES {
+        let img = common::cover(size);
+        let dwt = DWTImage::from_ppm(&img);
+        assert_eq!(planar_forward(&img).ll, dwt.ll);
+        assert_eq!(planar_inverse(&dwt), dwt.inverse_dwt_f64().data);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::new("from_ppm", size), &img, |b, img| {
+            b.iter(|| DWTImage::from_ppm(black_box(img)))
+        });
+        group.bench_with_input(BenchmarkId::new("from_ppm_planar", size), &img, |b, img| {
+            b.iter(|| planar_forward(black_box(img)))
+        });
+        group.bench_with_input(BenchmarkId::new("inverse_dwt_f64", size), &dwt, |b, dwt| {
+            b.iter(|| black_box(dwt).inverse_dwt_f64())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("inverse_dwt_f64_planar", size),
+            &dwt,
+            |b, dwt| b.iter(|| planar_inverse(black_box(dwt))),
+        );
+        group.bench_with_input(BenchmarkId::new("inverse_dwt", size), &dwt, |b, dwt| {
+            b.iter(|| black_box(dwt).inverse_dwt::<RGBColor<i32>>(&Quantization::default()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, forward, inverse, dwt_image);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use idwteg::codecs::{self, tiff};
+use idwteg::color::RGBColor;
+use idwteg::pixel::RGBAColor;
+
+// PNG, TIFF, QOI, BMP, TGA, PFM and netpbm, from a slice and streamed
+fuzz_target!(|data: &[u8]| {
+    let _ = codecs::decode_image::<RGBColor<i32>>(data);
+    let _ = codecs::read_image::<RGBAColor<u16>, _>(data);
+    let _ = tiff::decode_pages::<RGBColor<u8>>(data);
+});
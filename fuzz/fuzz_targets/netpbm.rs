@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use idwteg::color::RGBColor;
+use idwteg::image::PPMImage;
+use idwteg::pixel::{GrayColor, RGBAColor};
+
+// Every layout, the header decides how the samples are spread over the channels
+fuzz_target!(|data: &[u8]| {
+    let _ = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(data);
+    let _ = PPMImage::<GrayColor<u8>>::from_netpbm_bytes(data);
+    let _ = PPMImage::<RGBAColor<f32>>::from_netpbm_bytes(data);
+
+    // written back and read again, anything accepted has to survive that unchanged
+    if let Ok(img) = PPMImage::<RGBAColor<u16>>::read_netpbm(data) {
+        let mut bytes = Vec::new();
+        img.write_netpbm(&mut bytes).unwrap();
+        let read = PPMImage::<RGBAColor<u16>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(read.data, img.data);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use idwteg::algorithm::StegoKey;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(key) = StegoKey::from_bytes(data) {
+        assert_eq!(key.to_bytes(), data);
+    }
+});
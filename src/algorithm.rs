@@ -0,0 +1,371 @@
+use std::io::{Error, ErrorKind};
+
+use crate::codecs;
+use crate::color::RGBColor;
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::Pixel;
+
+const KEY_MAGIC: &[u8; 4] = b"IDWK";
+const KEY_VERSION: u8 = 1;
+// Secret samples are always stored on 8 bits by the bit stream algorithms
+pub(crate) const BITS_PER_SECRET_PIXEL: usize = 24;
+
+// Cover, secret and stego image share the pixel layout `P`
+pub trait StegoAlgorithm<P = RGBColor<i32>> {
+    // Hides `secret` in `cover`, returns the stego image and what is needed to get it back
+    fn embed(
+        &self,
+        cover: &PPMImage<P>,
+        secret: &PPMImage<P>,
+    ) -> std::io::Result<(PPMImage<P>, StegoKey)>;
+
+    fn extract(&self, stego: &PPMImage<P>, key: &StegoKey) -> std::io::Result<PPMImage<P>>;
+
+    // Number of secret pixels `cover` can carry
+    fn capacity(&self, cover: &PPMImage<P>) -> usize;
+}
+
+// Everything besides the stego image an algorithm needs for extraction. Spatial algorithms
+// only use the secret dimensions, the DWT scheme also records where each block went.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StegoKey {
+    pub width: usize,
+    pub height: usize,
+    // matched LL block of the cover, per secret block
+    pub block_indexes: Vec<usize>,
+    // (sub-band, block * channels + carrier) holding each channel of the difference block, per
+    // secret block
+    pub replaced_blocks: Vec<(usize, usize)>,
+}
+
+impl StegoKey {
+    pub fn new(width: usize, height: usize) -> StegoKey {
+        return StegoKey {
+            width,
+            height,
+            block_indexes: Vec::new(),
+            replaced_blocks: Vec::new(),
+        };
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(KEY_MAGIC);
+        buffer.push(KEY_VERSION);
+        buffer.extend_from_slice(&(self.width as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.height as u32).to_le_bytes());
+
+        buffer.extend_from_slice(&(self.block_indexes.len() as u32).to_le_bytes());
+        for index in &self.block_indexes {
+            buffer.extend_from_slice(&(*index as u32).to_le_bytes());
+        }
+
+        buffer.extend_from_slice(&(self.replaced_blocks.len() as u32).to_le_bytes());
+        for (band, index) in &self.replaced_blocks {
+            buffer.push(*band as u8);
+            buffer.extend_from_slice(&(*index as u32).to_le_bytes());
+        }
+
+        return buffer;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<StegoKey> {
+        return StegoKey::from_bytes_with_limits(bytes, &Limits::default());
+    }
+
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &Limits) -> std::io::Result<StegoKey> {
+        limits.check_key(bytes.len())?;
+        let mut reader = KeyReader::new(bytes);
+        if reader.take(4)? != KEY_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a stego key"));
+        }
+        let version = reader.take(1)?[0];
+        if version != KEY_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported stego key version {}", version),
+            ));
+        }
+
+        let mut key = StegoKey::new(reader.read_u32()?, reader.read_u32()?);
+        if key.pixels().is_none_or(|pixels| pixels > limits.max_pixels) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Stego key secret of {}x{} pixels is over the limit of {} pixels",
+                    key.width, key.height, limits.max_pixels
+                ),
+            ));
+        }
+        let count = reader.read_u32()?;
+        for _ in 0..count {
+            key.block_indexes.push(reader.read_u32()?);
+        }
+        let count = reader.read_u32()?;
+        for _ in 0..count {
+            let band = reader.take(1)?[0] as usize;
+            key.replaced_blocks.push((band, reader.read_u32()?));
+        }
+
+        if !reader.is_done() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Trailing bytes after stego key",
+            ));
+        }
+        return Ok(key);
+    }
+
+    pub fn pixels(&self) -> Option<usize> {
+        return self.width.checked_mul(self.height);
+    }
+}
+
+pub(crate) struct KeyReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl KeyReader<'_> {
+    pub(crate) fn new(bytes: &[u8]) -> KeyReader<'_> {
+        return KeyReader { bytes, position: 0 };
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        return self.position == self.bytes.len();
+    }
+
+    pub(crate) fn take(&mut self, count: usize) -> std::io::Result<&[u8]> {
+        if self.bytes.len() - self.position < count {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated stego key"));
+        }
+        self.position += count;
+        return Ok(&self.bytes[self.position - count..self.position]);
+    }
+
+    pub(crate) fn read_u32(&mut self) -> std::io::Result<usize> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize);
+    }
+}
+
+// Pixels of the secret `key` describes, refused above the `carried` pixels the stego image can
+// hold so that a forged key cannot make extraction allocate for a secret that is not there
+pub(crate) fn secret_pixels(key: &StegoKey, carried: usize) -> std::io::Result<usize> {
+    return key
+        .pixels()
+        .filter(|&pixels| pixels <= carried)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Stego key asks for more pixels than the image can carry",
+            )
+        });
+}
+
+// Secret samples scaled to 8 bits, in raster order, for algorithms that hide a bit stream
+pub(crate) fn secret_to_bytes(secret: &PPMImage) -> Vec<u8> {
+    let mut payload = Vec::<u8>::with_capacity(secret.data.len() * 3);
+    for color in &secret.data {
+        for c in 0..3 {
+            let value = color.channel(c).clamp(0, secret.max_val as i32) as usize;
+            if secret.max_val == 255 || secret.max_val == 0 {
+                payload.push(value as u8);
+            } else {
+                payload.push((value * 255 / secret.max_val) as u8);
+            }
+        }
+    }
+    return payload;
+}
+
+pub(crate) fn secret_from_bytes(width: usize, height: usize, payload: &[u8]) -> PPMImage {
+    let mut secret = PPMImage {
+        img_type: String::from("P6"),
+        width,
+        height,
+        max_val: 255,
+        data: Vec::with_capacity(width * height),
+    };
+    for pixel in payload.chunks_exact(3) {
+        secret
+            .data
+            .push(RGBColor::new(pixel[0], pixel[1], pixel[2]).into());
+    }
+    return secret;
+}
+
+// Most significant bit first
+pub(crate) fn bit_of(payload: &[u8], index: usize) -> u8 {
+    return (payload[index / 8] >> (7 - index % 8)) & 1;
+}
+
+pub(crate) fn set_bit(payload: &mut [u8], index: usize, bit: u8) {
+    payload[index / 8] |= (bit & 1) << (7 - index % 8);
+}
+
+// Both images are read as `P` whatever their format and layout, the stego image is written in
+// the format of the `output_file_path` extension, see `codecs::save_image`
+pub fn hide_image<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    cover_file_path: &str,
+    secret_file_path: &str,
+    output_file_path: &str,
+) -> std::io::Result<StegoKey> {
+    let cover = codecs::load_image(cover_file_path)?;
+    let secret = codecs::load_image(secret_file_path)?;
+    let (stego, key) = algorithm.embed(&cover, &secret)?;
+    codecs::save_image(stego, output_file_path)?;
+
+    return Ok(key);
+}
+
+pub fn extract_image<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    stego_file_path: &str,
+    output_file_path: &str,
+    key: &StegoKey,
+) -> std::io::Result<()> {
+    let stego = codecs::load_image(stego_file_path)?;
+    codecs::save_image(algorithm.extract(&stego, key)?, output_file_path)?;
+
+    return Ok(());
+}
+
+// Hides `secret` across several covers, e.g. the pages of a multi-page TIFF. The secret is cut
+// into horizontal strips of whole 4 row blocks, each page taking as many rows as it can carry,
+// and every strip is hidden in its page on its own. Key i goes with page i, a page too small for
+// a strip gets an empty key and pages past the last strip are left untouched and have no key.
+pub fn embed_in_pages<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    pages: &[PPMImage<P>],
+    secret: &PPMImage<P>,
+) -> std::io::Result<(Vec<PPMImage<P>>, Vec<StegoKey>)> {
+    let mut stego_pages = Vec::with_capacity(pages.len());
+    let mut keys = Vec::new();
+    let mut row = 0;
+
+    for page in pages {
+        let rows = strip_rows(algorithm, page, secret, row);
+        if rows == 0 {
+            if row < secret.height {
+                keys.push(StegoKey::new(secret.width, 0));
+            }
+            stego_pages.push(page.clone());
+            continue;
+        }
+
+        let (stego, key) = algorithm.embed(page, &secret_strip(secret, row, rows))?;
+        stego_pages.push(stego);
+        keys.push(key);
+        row += rows;
+    }
+
+    if row < secret.height {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Secret image is too large for these pages",
+        ));
+    }
+    return Ok((stego_pages, keys));
+}
+
+// Rows of `secret` from `row` on that `cover` takes, a multiple of 4 unless it is the last strip
+pub(crate) fn strip_rows<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    cover: &PPMImage<P>,
+    secret: &PPMImage<P>,
+    row: usize,
+) -> usize {
+    let remaining = secret.height - row;
+    let fitting = algorithm.capacity(cover) / secret.width.max(1) / 4 * 4;
+    let rows = remaining.min(fitting);
+    // the next cover always gets a whole block to work with
+    if rows < remaining && remaining - rows < 4 {
+        return rows.saturating_sub(4);
+    }
+    return rows;
+}
+
+pub(crate) fn secret_strip<P: Pixel>(secret: &PPMImage<P>, row: usize, rows: usize) -> PPMImage<P> {
+    return PPMImage {
+        img_type: secret.img_type.clone(),
+        width: secret.width,
+        height: rows,
+        max_val: secret.max_val,
+        data: secret.data[row * secret.width..(row + rows) * secret.width].to_vec(),
+    };
+}
+
+// Appends `strip` below the rows of `secret`, the first strip sets its width and type
+pub(crate) fn stack_strip<P: Pixel>(
+    secret: &mut PPMImage<P>,
+    strip: PPMImage<P>,
+) -> std::io::Result<()> {
+    if secret.data.is_empty() {
+        secret.img_type = strip.img_type;
+        secret.width = strip.width;
+        secret.max_val = strip.max_val;
+    } else if strip.width != secret.width {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Stego keys do not agree on the secret width",
+        ));
+    }
+    secret.height += strip.height;
+    secret.data.extend(strip.data);
+    return Ok(());
+}
+
+// Extracts the strip of every page with a non-empty key and stacks them back into the secret
+pub fn extract_from_pages<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    pages: &[PPMImage<P>],
+    keys: &[StegoKey],
+) -> std::io::Result<PPMImage<P>> {
+    if keys.is_empty() || keys.len() > pages.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Expected one stego key per page holding the secret",
+        ));
+    }
+
+    let mut secret = PPMImage::new();
+    for (page, key) in pages.iter().zip(keys) {
+        if key.height > 0 {
+            stack_strip(&mut secret, algorithm.extract(page, key)?)?;
+        }
+    }
+    return Ok(secret);
+}
+
+// `embed_in_pages` on the pages of a TIFF file, the stego pages are written to a TIFF file too
+pub fn hide_image_in_pages<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    cover_file_path: &str,
+    secret_file_path: &str,
+    output_file_path: &str,
+) -> std::io::Result<Vec<StegoKey>> {
+    let pages = codecs::tiff::load_pages(cover_file_path)?;
+    let secret = codecs::load_image(secret_file_path)?;
+    let (stego_pages, keys) = embed_in_pages(algorithm, &pages, &secret)?;
+    codecs::tiff::save_pages(&stego_pages, output_file_path)?;
+
+    return Ok(keys);
+}
+
+pub fn extract_image_from_pages<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    stego_file_path: &str,
+    output_file_path: &str,
+    keys: &[StegoKey],
+) -> std::io::Result<()> {
+    let pages = codecs::tiff::load_pages(stego_file_path)?;
+    codecs::save_image(
+        extract_from_pages(algorithm, &pages, keys)?,
+        output_file_path,
+    )?;
+
+    return Ok(());
+}
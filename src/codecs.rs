@@ -0,0 +1,462 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, Primitive};
+
+pub mod bmp;
+pub mod pfm;
+pub mod png;
+pub mod qoi;
+pub mod tga;
+pub mod tiff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Netpbm,
+    Png,
+    Bmp,
+    Tga,
+    Tiff,
+    Qoi,
+    Pfm,
+    Jpeg,
+    WebP,
+}
+
+impl ImageFormat {
+    // The format comes from the magic bytes, not the extension. Older TGA files have no magic
+    // and are recognized by a plausible header, so they are tried last.
+    pub fn detect(bytes: &[u8]) -> Option<ImageFormat> {
+        if bytes.starts_with(png::SIGNATURE) {
+            return Some(ImageFormat::Png);
+        } else if bytes.starts_with(bmp::SIGNATURE) {
+            return Some(ImageFormat::Bmp);
+        } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            return Some(ImageFormat::Tiff);
+        } else if bytes.starts_with(qoi::SIGNATURE) {
+            return Some(ImageFormat::Qoi);
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            return Some(ImageFormat::Jpeg);
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+            return Some(ImageFormat::WebP);
+        } else if bytes.starts_with(b"PF") || bytes.starts_with(b"Pf") {
+            return Some(ImageFormat::Pfm);
+        } else if bytes.len() >= 2 && bytes[0] == b'P' && (b'1'..=b'7').contains(&bytes[1]) {
+            return Some(ImageFormat::Netpbm);
+        } else if bytes.ends_with(tga::FOOTER_SIGNATURE) || tga::plausible_header(bytes) {
+            return Some(ImageFormat::Tga);
+        }
+        return None;
+    }
+
+    pub fn from_extension(file_path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(file_path).extension()?.to_str()?;
+        return match extension.to_ascii_lowercase().as_str() {
+            "pbm" | "pgm" | "ppm" | "pnm" | "pam" => Some(ImageFormat::Netpbm),
+            "png" => Some(ImageFormat::Png),
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "tga" | "icb" | "vda" | "vst" => Some(ImageFormat::Tga),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "qoi" => Some(ImageFormat::Qoi),
+            "pfm" => Some(ImageFormat::Pfm),
+            "jpg" | "jpeg" | "jpe" | "jfif" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::WebP),
+            _ => None,
+        };
+    }
+
+    // Lossy formats change the pixels when saving and destroy a hidden message
+    pub fn is_lossy(&self) -> bool {
+        return matches!(self, ImageFormat::Jpeg | ImageFormat::WebP);
+    }
+
+    // Whether samples going up to `max_val` are written and read back as they are. The 8 bit
+    // formats only store 255 and PNG and TIFF 255 or 65535, other ranges are rescaled.
+    pub fn stores_max_val(&self, max_val: usize) -> bool {
+        return match self {
+            ImageFormat::Netpbm | ImageFormat::Pfm => (1..=65535).contains(&max_val),
+            ImageFormat::Png | ImageFormat::Tiff => max_val == 255 || max_val == 65535,
+            _ => max_val == 255,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ImageFormat::Netpbm => "Netpbm",
+            ImageFormat::Png => "PNG",
+            ImageFormat::Bmp => "BMP",
+            ImageFormat::Tga => "TGA",
+            ImageFormat::Tiff => "TIFF",
+            ImageFormat::Qoi => "QOI",
+            ImageFormat::Pfm => "PFM",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::WebP => "WebP",
+        };
+    }
+
+    fn unsupported(&self) -> Error {
+        return Error::new(
+            ErrorKind::Unsupported,
+            format!("{} images are not supported", self.name()),
+        );
+    }
+}
+
+pub fn load_image<P: Pixel>(file_path: &str) -> std::io::Result<PPMImage<P>> {
+    return load_image_with_limits(file_path, &Limits::default());
+}
+
+pub fn load_image_with_limits<P: Pixel>(
+    file_path: &str,
+    limits: &Limits,
+) -> std::io::Result<PPMImage<P>> {
+    return read_image_with_limits(std::fs::File::open(file_path)?, limits);
+}
+
+pub fn read_image<P: Pixel, R: Read>(reader: R) -> std::io::Result<PPMImage<P>> {
+    return read_image_with_limits(reader, &Limits::default());
+}
+
+// Netpbm and PFM images are parsed as they stream in, the other formats need the whole input
+// (TGA can only be recognized by its footer) and are read to the end first, up to
+// `limits.max_bytes`
+pub fn read_image_with_limits<P: Pixel, R: Read>(
+    mut reader: R,
+    limits: &Limits,
+) -> std::io::Result<PPMImage<P>> {
+    let mut magic = [0; 2];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => Error::new(ErrorKind::InvalidData, "Unknown image format"),
+            _ => err,
+        })?;
+    let stream = BufReader::new(Cursor::new(magic).chain(reader));
+    return match ImageFormat::detect(&magic) {
+        Some(ImageFormat::Netpbm) => PPMImage::read_netpbm_with_limits(stream, limits),
+        Some(ImageFormat::Pfm) => Ok(from_float(PPMImage::read_pfm_with_limits(stream, limits)?)),
+        _ => {
+            let mut bytes = Vec::new();
+            stream
+                .into_inner()
+                .take(limits.max_bytes.saturating_add(1) as u64)
+                .read_to_end(&mut bytes)?;
+            limits.check_input(bytes.len())?;
+            decode_image_with_limits(&bytes, limits)
+        }
+    };
+}
+
+pub fn decode_image<P: Pixel>(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+    return decode_image_with_limits(bytes, &Limits::default());
+}
+
+pub fn decode_image_with_limits<P: Pixel>(
+    bytes: &[u8],
+    limits: &Limits,
+) -> std::io::Result<PPMImage<P>> {
+    let format = ImageFormat::detect(bytes)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown image format"))?;
+    return match format {
+        ImageFormat::Netpbm => PPMImage::read_netpbm_with_limits(bytes, limits),
+        ImageFormat::Png => PPMImage::decode_png(bytes, limits),
+        ImageFormat::Bmp => PPMImage::decode_bmp(bytes, limits),
+        ImageFormat::Tga => PPMImage::decode_tga(bytes, limits),
+        ImageFormat::Tiff => PPMImage::decode_tiff(bytes, limits),
+        ImageFormat::Qoi => PPMImage::decode_qoi(bytes, limits),
+        ImageFormat::Pfm => Ok(from_float(PPMImage::read_pfm_with_limits(bytes, limits)?)),
+        _ => Err(format.unsupported()),
+    };
+}
+
+// The format comes from the extension, netpbm when there is none or it is unknown. Lossy
+// formats are refused, see `save_image_as`.
+pub fn save_image<P: Pixel>(img: PPMImage<P>, file_path: &str) -> std::io::Result<()> {
+    let format = ImageFormat::from_extension(file_path).unwrap_or(ImageFormat::Netpbm);
+    return save_image_as(img, file_path, format, false);
+}
+
+// A stego image saved in a lossy format, or in one that cannot store its max_val, loses its
+// message, `allow_lossy` has to be set to write one anyway
+pub fn save_image_as<P: Pixel>(
+    img: PPMImage<P>,
+    file_path: &str,
+    format: ImageFormat,
+    allow_lossy: bool,
+) -> std::io::Result<()> {
+    if !allow_lossy {
+        check_lossless(format, img.max_val)?;
+    }
+    return match format {
+        ImageFormat::Netpbm => img.export_to_file(file_path),
+        ImageFormat::Png => img.export_to_png(file_path),
+        ImageFormat::Bmp => img.export_to_bmp(file_path),
+        ImageFormat::Tga => img.export_to_tga(file_path),
+        ImageFormat::Tiff => img.export_to_tiff(file_path),
+        ImageFormat::Qoi => img.export_to_qoi(file_path),
+        ImageFormat::Pfm => to_float(&img).export_to_pfm(file_path),
+        _ => Err(format.unsupported()),
+    };
+}
+
+// `save_image_as` for any writer, e.g. stdout or an in-memory buffer. TIFF needs to seek and is
+// encoded in memory first.
+pub fn write_image<P: Pixel, W: Write>(
+    img: &PPMImage<P>,
+    mut writer: W,
+    format: ImageFormat,
+    allow_lossy: bool,
+) -> std::io::Result<()> {
+    if !allow_lossy {
+        check_lossless(format, img.max_val)?;
+    }
+    return match format {
+        ImageFormat::Netpbm => img.write_netpbm(writer),
+        ImageFormat::Png => img.write_png(writer),
+        ImageFormat::Bmp => img.write_bmp(writer),
+        ImageFormat::Tga => img.write_tga(writer),
+        ImageFormat::Tiff => {
+            let mut buffer = Cursor::new(Vec::new());
+            img.write_tiff(&mut buffer)?;
+            writer.write_all(buffer.get_ref())?;
+            writer.flush()
+        }
+        ImageFormat::Qoi => img.write_qoi(writer),
+        ImageFormat::Pfm => to_float(img).write_pfm(writer),
+        _ => Err(format.unsupported()),
+    };
+}
+
+pub(crate) fn check_lossless(format: ImageFormat, max_val: usize) -> std::io::Result<()> {
+    if format.is_lossy() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} is lossy and would destroy the hidden message, use a lossless format",
+                format.name()
+            ),
+        ));
+    }
+    if !format.stores_max_val(max_val) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} cannot store samples up to {} as they are and would destroy the hidden \
+                 message, use Netpbm",
+                format.name(),
+                max_val
+            ),
+        ));
+    }
+    return Ok(());
+}
+
+// Float samples clamped to 0..=max_val and rounded, for float formats read into the pipeline
+fn from_float<P: Pixel>(img: PPMImage<P::Of<f64>>) -> PPMImage<P> {
+    let max_val = pixel::stored_max_val::<P>(img.max_val);
+    let scale = max_val as f64 / img.max_val.max(1) as f64;
+    return PPMImage {
+        img_type: String::from(PPMImage::<P>::default_img_type()),
+        width: img.width,
+        height: img.height,
+        max_val,
+        data: img
+            .data
+            .into_iter()
+            .map(|color| {
+                color.convert(|sample| {
+                    Primitive::from_f64((sample * scale).round().clamp(0.0, max_val as f64))
+                })
+            })
+            .collect(),
+    };
+}
+
+fn to_float<P: Pixel>(img: &PPMImage<P>) -> PPMImage<P::Of<f64>> {
+    return PPMImage {
+        img_type: img.img_type.clone(),
+        width: img.width,
+        height: img.height,
+        max_val: img.max_val,
+        data: img
+            .data
+            .iter()
+            .map(|color| color.convert(Primitive::to_f64))
+            .collect(),
+    };
+}
+
+// Writes a temporary file next to `file_path` and renames it over the target once the data is
+// flushed and synced to disk, a crash leaves the old file or the new one but never half of one
+pub(crate) fn write_atomically<F>(file_path: &str, write: F) -> std::io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<fs::File>) -> std::io::Result<()>,
+{
+    let path = Path::new(file_path);
+    let temporary_path = temporary_path(path)?;
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary_path)?;
+    let mut writer = BufWriter::new(file);
+    let result = write(&mut writer)
+        .and_then(|_| writer.into_inner().map_err(|err| err.into_error()))
+        .and_then(|file| file.sync_all())
+        .and_then(|_| fs::rename(&temporary_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    return result;
+}
+
+// Hidden file next to `path`, unique within and across processes, so that it can be renamed over
+// `path` once complete
+pub(crate) fn temporary_path(path: &Path) -> std::io::Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Output path has no file name"))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    return Ok(path.with_file_name(temporary_name));
+}
+
+// `sample` of an image going up to `max_val` on 0..=target, rounded
+pub(crate) fn rescale(sample: i32, max_val: usize, target: usize) -> usize {
+    let max_val = max_val.max(1);
+    let sample = (sample.max(0) as usize).min(max_val);
+    if max_val == target {
+        return sample;
+    }
+    return (sample * target + max_val / 2) / max_val;
+}
+
+pub(crate) fn read_u16_le(bytes: &[u8], offset: usize) -> std::io::Result<u16> {
+    return match bytes.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Truncated image header",
+        )),
+    };
+}
+
+pub(crate) fn read_u32_le(bytes: &[u8], offset: usize) -> std::io::Result<u32> {
+    return match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Truncated image header",
+        )),
+    };
+}
+
+// Test images for the codecs, and the files of tests/fixtures for the variants the encoders do
+// not write
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::image::PPMImage;
+    use crate::pixel::{Pixel, Primitive, RGBAColor};
+
+    // Every fixture is this 9x5 pattern, stored in its own way
+    pub(crate) const WIDTH: usize = 9;
+    pub(crate) const HEIGHT: usize = 5;
+
+    pub(crate) fn pattern(x: usize, y: usize) -> RGBAColor<i32> {
+        let (x, y) = (x as i32, y as i32);
+        return RGBAColor::new(
+            (x * 29 + y * 3) % 256,
+            (y * 31 + x * 7) % 256,
+            (x * y * 11 + 50) % 256,
+            255,
+        );
+    }
+
+    pub(crate) fn read(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        return std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path, err));
+    }
+
+    // Samples all over 0..=max_val, including both ends, in every channel of `P`
+    pub(crate) fn image<P: Pixel>(width: usize, height: usize, max_val: usize) -> PPMImage<P> {
+        let mut img = PPMImage::<P>::new();
+        img.img_type = String::from(PPMImage::<P>::default_img_type());
+        (img.width, img.height, img.max_val) = (width, height, max_val);
+        let max_val = max_val as u64;
+        for i in 0..(width * height) as u64 {
+            let mut color = P::default();
+            for c in 0..P::CHANNELS as u64 {
+                let sample = match (i + c) % 5 {
+                    0 => 0,
+                    1 => max_val,
+                    _ => (i * 7919 + c * 104729) % (max_val + 1),
+                };
+                *color.channel_mut(c as usize) = P::Sample::from_i32(sample as i32);
+            }
+            img.data.push(color);
+        }
+        return img;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::RGBColor;
+
+    fn image(max_val: usize) -> PPMImage {
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (4, 2, max_val);
+        let max_val = max_val as i32;
+        for i in 0..8 {
+            img.data
+                .push(RGBColor::new(i * max_val / 7, max_val - i, i % 2 * max_val));
+        }
+        return img;
+    }
+
+    #[test]
+    fn depths_a_format_cannot_store_are_refused() {
+        let formats = [
+            ImageFormat::Netpbm,
+            ImageFormat::Png,
+            ImageFormat::Bmp,
+            ImageFormat::Tga,
+            ImageFormat::Tiff,
+            ImageFormat::Qoi,
+            ImageFormat::Pfm,
+        ];
+        for max_val in [255, 1023, 65535] {
+            let img = image(max_val);
+            for format in formats {
+                let mut bytes = Vec::new();
+                match write_image(&img, &mut bytes, format, false) {
+                    Ok(()) => {
+                        assert!(format.stores_max_val(max_val), "{}", format.name());
+                        let read: PPMImage = decode_image(&bytes).unwrap();
+                        assert_eq!(read.max_val, max_val, "{}", format.name());
+                        assert_eq!(read.data, img.data, "{}", format.name());
+                    }
+                    Err(err) => {
+                        assert!(!format.stores_max_val(max_val), "{}", format.name());
+                        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+                        assert!(write_image(&img, &mut Vec::new(), format, true).is_ok());
+                    }
+                }
+            }
+        }
+        assert!(write_image(&image(255), Vec::new(), ImageFormat::Jpeg, false).is_err());
+    }
+}
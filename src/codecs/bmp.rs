@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+
+use crate::codecs::{self, read_u16_le, read_u32_le};
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, RGBAColor};
+
+pub(crate) const SIGNATURE: &[u8] = b"BM";
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+// BITMAPV4HEADER, the smallest header with an alpha mask
+const V4_HEADER_SIZE: usize = 108;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+impl<P: Pixel> PPMImage<P> {
+    // Uncompressed 24 and 32 bit bitmaps, bottom-up or top-down (negative height). 32 bit
+    // bitmaps only have alpha when their channel masks give one.
+    pub fn from_bmp(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::from_bmp_bytes(&fs::read(file_path)?);
+    }
+
+    pub fn from_bmp_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::decode_bmp(bytes, &Limits::default());
+    }
+
+    pub(crate) fn decode_bmp(bytes: &[u8], limits: &Limits) -> std::io::Result<PPMImage<P>> {
+        if !bytes.starts_with(SIGNATURE) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a BMP image"));
+        }
+        let offset = read_u32_le(bytes, 10)? as usize;
+        let header_size = read_u32_le(bytes, 14)? as usize;
+        if header_size < INFO_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "OS/2 bitmaps are not supported",
+            ));
+        }
+        let width = read_u32_le(bytes, 18)? as i32;
+        let height = read_u32_le(bytes, 22)? as i32;
+        let bits_per_pixel = read_u16_le(bytes, 28)?;
+        let compression = read_u32_le(bytes, 30)?;
+
+        // red, green, blue and alpha masks
+        let masks = match (bits_per_pixel, compression) {
+            (24 | 32, BI_RGB) => [0xff0000, 0xff00, 0xff, 0],
+            (32, BI_BITFIELDS | BI_ALPHABITFIELDS) => {
+                let has_alpha = compression == BI_ALPHABITFIELDS || header_size >= 56;
+                [
+                    read_u32_le(bytes, 54)?,
+                    read_u32_le(bytes, 58)?,
+                    read_u32_le(bytes, 62)?,
+                    if has_alpha {
+                        read_u32_le(bytes, 66)?
+                    } else {
+                        0
+                    },
+                ]
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Unsupported BMP with {} bits per pixel and compression {}",
+                        bits_per_pixel, compression
+                    ),
+                ))
+            }
+        };
+        if width <= 0 || height == 0 || height == i32::MIN {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid BMP dimensions"));
+        }
+
+        let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+        limits.check_image::<P>(width, rows)?;
+        let pixel_bytes = bits_per_pixel as usize / 8;
+        let row_size = (width * pixel_bytes).div_ceil(4) * 4;
+        let raster = row_size
+            .checked_mul(rows)
+            .and_then(|size| bytes.get(offset..offset.checked_add(size)?))
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Truncated BMP image"))?;
+
+        let mut img = PPMImage {
+            img_type: String::from(PPMImage::<P>::default_img_type()),
+            width,
+            height: rows,
+            max_val: 255,
+            data: Vec::with_capacity(width * rows),
+        };
+        for y in 0..rows {
+            // bottom-up unless the height is negative
+            let row = if height > 0 { rows - 1 - y } else { y };
+            let row = &raster[row * row_size..row * row_size + width * pixel_bytes];
+            for pixel in row.chunks_exact(pixel_bytes) {
+                let value = if pixel_bytes == 3 {
+                    u32::from_le_bytes([pixel[0], pixel[1], pixel[2], 0])
+                } else {
+                    u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+                };
+                let color = RGBAColor::new(
+                    masked_channel(value, masks[0]).unwrap_or(0),
+                    masked_channel(value, masks[1]).unwrap_or(0),
+                    masked_channel(value, masks[2]).unwrap_or(0),
+                    masked_channel(value, masks[3]).unwrap_or(255),
+                );
+                img.data.push(pixel::from_rgba(&color, 255));
+            }
+        }
+
+        return Ok(img);
+    }
+
+    // Bottom-up, 24 bit for layouts without alpha and 32 bit with a V4 header giving the alpha
+    // mask otherwise. Samples are rescaled to 8 bits.
+    pub fn export_to_bmp(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_bmp(writer));
+    }
+
+    pub fn write_bmp<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let alpha = P::has_alpha();
+        let (header_size, pixel_bytes) = if alpha {
+            (V4_HEADER_SIZE, 4)
+        } else {
+            (INFO_HEADER_SIZE, 3)
+        };
+        let row_size = (self.width * pixel_bytes).div_ceil(4) * 4;
+        let offset = FILE_HEADER_SIZE + header_size;
+        let file_size = offset + row_size * self.height;
+
+        let mut buffer = Vec::with_capacity(file_size);
+        buffer.extend_from_slice(SIGNATURE);
+        buffer.extend_from_slice(&(file_size as u32).to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        buffer.extend_from_slice(&(offset as u32).to_le_bytes());
+
+        buffer.extend_from_slice(&(header_size as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.width as i32).to_le_bytes());
+        buffer.extend_from_slice(&(self.height as i32).to_le_bytes());
+        buffer.extend_from_slice(&1_u16.to_le_bytes()); // planes
+        buffer.extend_from_slice(&(pixel_bytes as u16 * 8).to_le_bytes());
+        let compression = if alpha { BI_BITFIELDS } else { BI_RGB };
+        buffer.extend_from_slice(&compression.to_le_bytes());
+        buffer.extend_from_slice(&((row_size * self.height) as u32).to_le_bytes());
+        // 72 DPI, no palette
+        buffer.extend_from_slice(&2835_u32.to_le_bytes());
+        buffer.extend_from_slice(&2835_u32.to_le_bytes());
+        buffer.extend_from_slice(&[0; 8]);
+        if alpha {
+            for mask in [0xff0000_u32, 0xff00, 0xff, 0xff000000] {
+                buffer.extend_from_slice(&mask.to_le_bytes());
+            }
+            buffer.extend_from_slice(b"BGRs"); // LCS_sRGB, stored little endian
+            buffer.resize(offset, 0); // endpoints and gamma, unused for sRGB
+        }
+
+        for y in (0..self.height).rev() {
+            let row_start = buffer.len();
+            for color in &self.data[y * self.width..(y + 1) * self.width] {
+                let color = pixel::to_rgba(color, self.max_val as i32);
+                buffer.push(codecs::rescale(color.blue, self.max_val, 255) as u8);
+                buffer.push(codecs::rescale(color.green, self.max_val, 255) as u8);
+                buffer.push(codecs::rescale(color.red, self.max_val, 255) as u8);
+                if alpha {
+                    buffer.push(codecs::rescale(color.alpha, self.max_val, 255) as u8);
+                }
+            }
+            buffer.resize(row_start + row_size, 0);
+        }
+
+        writer.write_all(&buffer)?;
+        return writer.flush();
+    }
+}
+
+// The bits of `value` under `mask`, scaled to 8 bits, None without a mask
+fn masked_channel(value: u32, mask: u32) -> Option<i32> {
+    if mask == 0 {
+        return None;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let channel = ((value & mask) >> shift) as u64;
+    return Some(((channel * 255 + max / 2) / max) as i32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures::{self, HEIGHT, WIDTH};
+    use crate::color::RGBColor;
+
+    fn assert_pattern(img: &PPMImage) {
+        assert_eq!((img.width, img.height, img.max_val), (WIDTH, HEIGHT, 255));
+        for (i, color) in img.data.iter().enumerate() {
+            let expected = fixtures::pattern(i % WIDTH, i / WIDTH);
+            let expected = RGBColor::new(expected.red, expected.green, expected.blue);
+            assert_eq!(*color, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn rgb_and_rgba_round_trip() {
+        // 7 pixels of 3 bytes need padding at the end of every row
+        let img = fixtures::image::<RGBColor<i32>>(7, 3, 255);
+        let mut bytes = Vec::new();
+        img.write_bmp(&mut bytes).unwrap();
+        assert_eq!(read_u16_le(&bytes, 28).unwrap(), 24);
+        assert_eq!(
+            PPMImage::<RGBColor<i32>>::from_bmp_bytes(&bytes)
+                .unwrap()
+                .data,
+            img.data
+        );
+
+        let img = fixtures::image::<RGBAColor<i32>>(7, 3, 255);
+        let mut bytes = Vec::new();
+        img.write_bmp(&mut bytes).unwrap();
+        assert_eq!(read_u16_le(&bytes, 28).unwrap(), 32);
+        assert_eq!(
+            PPMImage::<RGBAColor<i32>>::from_bmp_bytes(&bytes)
+                .unwrap()
+                .data,
+            img.data
+        );
+    }
+
+    #[test]
+    fn top_down_bitmaps() {
+        assert_pattern(&PPMImage::from_bmp_bytes(&fixtures::read("top_down.bmp")).unwrap());
+    }
+
+    #[test]
+    fn the_fourth_byte_of_32_bit_bitmaps_without_masks_is_not_alpha() {
+        let bytes = fixtures::read("rgb32.bmp");
+        assert_pattern(&PPMImage::from_bmp_bytes(&bytes).unwrap());
+        let img = PPMImage::<RGBAColor<i32>>::from_bmp_bytes(&bytes).unwrap();
+        assert!(img.data.iter().all(|color| color.alpha == 255));
+    }
+}
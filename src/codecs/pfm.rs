@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+
+use crate::codecs;
+use crate::image::{HeaderReader, PPMImage, PIXELS_PER_READ};
+use crate::limits::Limits;
+use crate::pixel::Pixel;
+
+impl<P: Pixel<Sample = f64>> PPMImage<P> {
+    // PF (RGB) and Pf (gray) float maps of either byte order. A gray file fills every color
+    // channel, an RGB file read as gray takes the BT.601 luma, alpha channels are set to max_val.
+    // The magnitude of the scale factor becomes max_val.
+    pub fn from_pfm(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_pfm(BufReader::new(fs::File::open(file_path)?));
+    }
+
+    pub fn from_pfm_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_pfm(bytes);
+    }
+
+    pub fn read_pfm<R: BufRead>(reader: R) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_pfm_with_limits(reader, &Limits::default());
+    }
+
+    pub fn read_pfm_with_limits<R: BufRead>(
+        reader: R,
+        limits: &Limits,
+    ) -> std::io::Result<PPMImage<P>> {
+        let mut reader = HeaderReader::new(reader);
+        let img_type = reader.next_token()?;
+        let channels = match img_type.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Not a PFM image")),
+        };
+        let (width, height) = (reader.next_number()?, reader.next_number()?);
+        let token = reader.next_token()?;
+        let scale: f32 = token.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected a scale factor in PFM header, got {:?}", token),
+            )
+        })?;
+        reader.read_exact(&mut [0])?;
+
+        if width == 0 || height == 0 || scale == 0.0 || !scale.is_finite() {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid PFM header"));
+        }
+        limits.check_image::<P>(width, height)?;
+
+        let max_val = (scale.abs().round() as usize).max(1);
+        let mut img = PPMImage {
+            img_type,
+            width,
+            height,
+            max_val,
+            data: Vec::with_capacity((width * height).min(1 << 20)),
+        };
+        let mut row = Vec::new();
+        // pieces of a row at a time like `read_netpbm`
+        for _ in 0..height {
+            for start in (0..width).step_by(PIXELS_PER_READ) {
+                row.resize(PIXELS_PER_READ.min(width - start) * channels * 4, 0);
+                reader.read_exact(&mut row)?;
+                for pixel in row.chunks_exact(channels * 4) {
+                    let mut samples = [0.0; 3];
+                    for (c, sample) in pixel.chunks_exact(4).enumerate() {
+                        let sample = [sample[0], sample[1], sample[2], sample[3]];
+                        // a negative scale means little endian
+                        samples[c] = if scale < 0.0 {
+                            f32::from_le_bytes(sample) as f64
+                        } else {
+                            f32::from_be_bytes(sample) as f64
+                        };
+                    }
+                    let mut color = P::default();
+                    if channels == 1 {
+                        for c in 0..P::COLOR_CHANNELS {
+                            *color.channel_mut(c) = samples[0];
+                        }
+                    } else if P::COLOR_CHANNELS == 1 {
+                        *color.channel_mut(0) =
+                            0.299 * samples[0] + 0.587 * samples[1] + 0.114 * samples[2];
+                    } else {
+                        for (c, &sample) in samples.iter().enumerate() {
+                            *color.channel_mut(c) = sample;
+                        }
+                    }
+                    if P::has_alpha() {
+                        *color.channel_mut(P::COLOR_CHANNELS) = max_val as f64;
+                    }
+                    img.data.push(color);
+                }
+            }
+        }
+
+        // rows are stored bottom-up
+        let rows: Vec<&[P]> = img.data.chunks_exact(width).rev().collect();
+        img.data = rows.concat();
+        return Ok(img);
+    }
+
+    // Little endian, samples are narrowed to f32 and max_val is written as the scale factor.
+    // PFM has no alpha channel, layouts with one are refused.
+    pub fn export_to_pfm(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_pfm(writer));
+    }
+
+    pub fn write_pfm<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        if P::has_alpha() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "PFM images cannot have an alpha channel",
+            ));
+        }
+        let img_type = if P::COLOR_CHANNELS == 1 { "Pf" } else { "PF" };
+        let mut buffer = format!(
+            "{}\n{} {}\n{}\n",
+            img_type,
+            self.width,
+            self.height,
+            -(self.max_val.max(1) as f64)
+        )
+        .into_bytes();
+        buffer.reserve(self.data.len() * P::CHANNELS * 4);
+
+        for y in (0..self.height).rev() {
+            for color in &self.data[y * self.width..(y + 1) * self.width] {
+                for c in 0..P::CHANNELS {
+                    buffer.extend_from_slice(&(color.channel(c) as f32).to_le_bytes());
+                }
+            }
+        }
+
+        writer.write_all(&buffer)?;
+        return writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures::{self, HEIGHT, WIDTH};
+    use crate::color::RGBColor;
+    use crate::pixel::{GrayColor, RGBAColor};
+
+    fn round_trip<P: Pixel<Sample = f64>>() {
+        let mut img = fixtures::image::<P>(7, 3, 255);
+        // quarters are exact in f32 too, unlike most fractions
+        for color in img.data.iter_mut() {
+            *color.channel_mut(0) += 0.25;
+        }
+        let mut bytes = Vec::new();
+        img.write_pfm(&mut bytes).unwrap();
+        let read = PPMImage::<P>::from_pfm_bytes(&bytes).unwrap();
+        assert_eq!((read.width, read.height), (7, 3));
+        assert_eq!(read.max_val, 255);
+        assert_eq!(read.data, img.data);
+    }
+
+    #[test]
+    fn gray_and_rgb_round_trip() {
+        round_trip::<GrayColor<f64>>();
+        round_trip::<RGBColor<f64>>();
+    }
+
+    #[test]
+    fn alpha_channels_are_refused() {
+        let img = fixtures::image::<RGBAColor<f64>>(7, 3, 255);
+        let err = img.write_pfm(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    // a positive scale factor means big endian, the writer never uses it
+    #[test]
+    fn big_endian_gray_images() {
+        let bytes = fixtures::read("big_endian.pfm");
+        let expected = |i: usize| fixtures::pattern(i % WIDTH, i / WIDTH).red as f64 / 2.0;
+
+        let img = PPMImage::<GrayColor<f64>>::from_pfm_bytes(&bytes).unwrap();
+        assert_eq!((img.width, img.height, img.max_val), (WIDTH, HEIGHT, 255));
+        for (i, color) in img.data.iter().enumerate() {
+            assert_eq!(color.gray, expected(i), "pixel {}", i);
+        }
+
+        // gray files fill every color channel
+        let img = PPMImage::<RGBColor<f64>>::from_pfm_bytes(&bytes).unwrap();
+        for (i, color) in img.data.iter().enumerate() {
+            assert_eq!(*color, RGBColor::new(expected(i), expected(i), expected(i)));
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Write};
+
+use png::{BitDepth, ColorType, Decoder, DecodingError, Encoder, Transformations};
+
+use crate::codecs;
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, Primitive, RGBAColor};
+
+pub(crate) const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// Deflate expands at most 1032 times and expanding 1 bit palette indexes to RGBA 8 bit samples 32
+// times, a file cannot hold more pixels than that
+const MAX_EXPANSION: usize = 1032 * 32;
+
+impl<P: Pixel> PPMImage<P> {
+    // 1 to 16 bit gray, gray + alpha, RGB, RGBA and palette images, interlaced or not. Palettes
+    // and bit depths below 8 are expanded to 8 bits, tRNS chunks become an alpha channel, and
+    // the pixels are converted to `P` like `from_netpbm` does.
+    pub fn from_png(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::from_png_bytes(&fs::read(file_path)?);
+    }
+
+    pub fn from_png_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::decode_png(bytes, &Limits::default());
+    }
+
+    pub(crate) fn decode_png(bytes: &[u8], limits: &Limits) -> std::io::Result<PPMImage<P>> {
+        let mut decoder = Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(decoding_error)?;
+        let info = reader.info();
+        limits.check_image::<P>(info.width as usize, info.height as usize)?;
+        // the decoder does not limit the output buffer, which the header alone sizes
+        let buffer_size = reader
+            .output_buffer_size()
+            .filter(|&size| size / MAX_EXPANSION <= bytes.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "PNG image is too large"))?;
+        let mut buffer = vec![0; buffer_size];
+        let frame = reader.next_frame(&mut buffer).map_err(decoding_error)?;
+
+        let (channels, alpha) = match frame.color_type {
+            ColorType::Grayscale => (1, false),
+            ColorType::GrayscaleAlpha => (2, true),
+            ColorType::Rgb => (3, false),
+            ColorType::Rgba => (4, true),
+            ColorType::Indexed => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "PNG palette was not expanded",
+                ))
+            }
+        };
+        let sample_bytes = if frame.bit_depth == BitDepth::Sixteen {
+            2
+        } else {
+            1
+        };
+        let max_val = if sample_bytes == 2 { 65535 } else { 255 };
+
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let mut img = PPMImage {
+            img_type: String::from(PPMImage::<P>::default_img_type()),
+            width,
+            height,
+            max_val: pixel::stored_max_val::<P>(max_val as usize),
+            data: Vec::with_capacity(width * height),
+        };
+        for y in 0..height {
+            let row = &buffer[y * frame.line_size..(y + 1) * frame.line_size];
+            for pixel in row.chunks_exact(channels * sample_bytes).take(width) {
+                let mut samples = [max_val; 4];
+                for (c, sample) in pixel.chunks_exact(sample_bytes).enumerate() {
+                    samples[c] = if sample_bytes == 1 {
+                        sample[0] as i32
+                    } else {
+                        u16::from_be_bytes([sample[0], sample[1]]) as i32
+                    };
+                }
+                let color = if channels <= 2 {
+                    let alpha = if alpha { samples[1] } else { max_val };
+                    RGBAColor::new(samples[0], samples[0], samples[0], alpha)
+                } else {
+                    RGBAColor::new(samples[0], samples[1], samples[2], samples[3])
+                };
+                img.data.push(pixel::from_rgba(&color, max_val as usize));
+            }
+        }
+
+        return Ok(img);
+    }
+
+    // Lossless for max_val 255 (8 bit) and 65535 (16 bit), other ranges are rescaled to the
+    // nearest of the two
+    pub fn export_to_png(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_png(writer));
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut encoder = Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(match (P::COLOR_CHANNELS, P::has_alpha()) {
+            (1, false) => ColorType::Grayscale,
+            (1, true) => ColorType::GrayscaleAlpha,
+            (_, false) => ColorType::Rgb,
+            (_, true) => ColorType::Rgba,
+        });
+        let wide = self.max_val > 255;
+        encoder.set_depth(if wide {
+            BitDepth::Sixteen
+        } else {
+            BitDepth::Eight
+        });
+
+        let target = if wide { 65535 } else { 255 };
+        let mut buffer = Vec::with_capacity(self.data.len() * P::CHANNELS * (1 + wide as usize));
+        for color in &self.data {
+            for c in 0..P::CHANNELS {
+                let sample = codecs::rescale(color.channel(c).to_i32(), self.max_val, target);
+                if wide {
+                    buffer.extend_from_slice(&(sample as u16).to_be_bytes());
+                } else {
+                    buffer.push(sample as u8);
+                }
+            }
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&buffer)?;
+        writer.finish()?;
+        return Ok(());
+    }
+}
+
+fn decoding_error(err: DecodingError) -> Error {
+    return match err {
+        DecodingError::IoError(err) => err,
+        err => Error::new(ErrorKind::InvalidData, err),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures::{self, HEIGHT, WIDTH};
+    use crate::color::RGBColor;
+    use crate::pixel::{GrayAlphaColor, GrayColor};
+
+    fn round_trip<P: Pixel>(max_val: usize) {
+        let img = fixtures::image::<P>(7, 3, max_val);
+        let mut bytes = Vec::new();
+        img.write_png(&mut bytes).unwrap();
+        let read = PPMImage::<P>::from_png_bytes(&bytes).unwrap();
+        assert_eq!((read.width, read.height, read.max_val), (7, 3, max_val));
+        assert_eq!(read.data, img.data, "{} channels", P::CHANNELS);
+    }
+
+    #[test]
+    fn every_layout_round_trips_on_8_and_16_bits() {
+        for max_val in [255, 65535] {
+            round_trip::<GrayColor<i32>>(max_val);
+            round_trip::<GrayAlphaColor<i32>>(max_val);
+            round_trip::<RGBColor<i32>>(max_val);
+            round_trip::<RGBAColor<i32>>(max_val);
+        }
+        round_trip::<RGBColor<u16>>(65535);
+    }
+
+    #[test]
+    fn palettes_are_expanded_with_their_transparency() {
+        // one palette entry per pixel, its tRNS alpha going down by 5 from 255
+        let img: PPMImage<RGBAColor<i32>> =
+            PPMImage::from_png_bytes(&fixtures::read("palette.png")).unwrap();
+        assert_eq!((img.width, img.height, img.max_val), (WIDTH, HEIGHT, 255));
+        for (i, color) in img.data.iter().enumerate() {
+            let mut expected = fixtures::pattern(i % WIDTH, i / WIDTH);
+            expected.alpha = 255 - 5 * i as i32;
+            assert_eq!(*color, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn interlaced_images_are_read_in_raster_order() {
+        let img: PPMImage<RGBAColor<i32>> =
+            PPMImage::from_png_bytes(&fixtures::read("interlaced.png")).unwrap();
+        assert_eq!((img.width, img.height), (WIDTH, HEIGHT));
+        for (i, color) in img.data.iter().enumerate() {
+            assert_eq!(
+                *color,
+                fixtures::pattern(i % WIDTH, i / WIDTH),
+                "pixel {}",
+                i
+            );
+        }
+    }
+}
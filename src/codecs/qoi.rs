@@ -0,0 +1,235 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+
+use crate::codecs;
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, RGBAColor};
+
+pub(crate) const SIGNATURE: &[u8] = b"qoif";
+const HEADER_SIZE: usize = 14;
+const END_MARKER: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 1];
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const OP_MASK: u8 = 0xc0;
+const MAX_RUN: usize = 62;
+
+impl<P: Pixel> PPMImage<P> {
+    // 8 bit RGB and RGBA, converted to `P` like `from_netpbm` does. The color space byte of the
+    // header only describes the samples and is ignored.
+    pub fn from_qoi(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::from_qoi_bytes(&fs::read(file_path)?);
+    }
+
+    pub fn from_qoi_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::decode_qoi(bytes, &Limits::default());
+    }
+
+    pub(crate) fn decode_qoi(bytes: &[u8], limits: &Limits) -> std::io::Result<PPMImage<P>> {
+        if !bytes.starts_with(SIGNATURE) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a QOI image"));
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated QOI header"));
+        }
+        let width = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let height = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        if width == 0 || height == 0 || !(3..=4).contains(&bytes[12]) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid QOI header"));
+        }
+        let pixels = limits.check_image::<P>(width, height)?;
+
+        let truncated = || Error::new(ErrorKind::UnexpectedEof, "Truncated QOI image");
+        let mut img = PPMImage {
+            img_type: String::from(PPMImage::<P>::default_img_type()),
+            width,
+            height,
+            max_val: 255,
+            data: Vec::with_capacity(pixels.min(bytes.len() * MAX_RUN)),
+        };
+        let mut index = [[0_u8; 4]; 64];
+        let mut previous = [0, 0, 0, 255_u8];
+        let mut position = HEADER_SIZE;
+        let mut run = 0;
+
+        while img.data.len() < pixels {
+            if run > 0 {
+                run -= 1;
+            } else {
+                let op = *bytes.get(position).ok_or_else(truncated)?;
+                position += 1;
+                match op {
+                    OP_RGB | OP_RGBA => {
+                        let count = if op == OP_RGB { 3 } else { 4 };
+                        let samples = bytes
+                            .get(position..position + count)
+                            .ok_or_else(truncated)?;
+                        previous[..count].copy_from_slice(samples);
+                        position += count;
+                    }
+                    _ => match op & OP_MASK {
+                        OP_INDEX => previous = index[op as usize],
+                        OP_DIFF => {
+                            for (c, shift) in [4, 2, 0].into_iter().enumerate() {
+                                let delta = (op >> shift & 0x03).wrapping_sub(2);
+                                previous[c] = previous[c].wrapping_add(delta);
+                            }
+                        }
+                        OP_LUMA => {
+                            let second = *bytes.get(position).ok_or_else(truncated)?;
+                            position += 1;
+                            let green = (op & 0x3f).wrapping_sub(32);
+                            let red = green.wrapping_add(second >> 4).wrapping_sub(8);
+                            let blue = green.wrapping_add(second & 0x0f).wrapping_sub(8);
+                            previous[0] = previous[0].wrapping_add(red);
+                            previous[1] = previous[1].wrapping_add(green);
+                            previous[2] = previous[2].wrapping_add(blue);
+                        }
+                        _ => run = (op & 0x3f) as usize,
+                    },
+                }
+                index[hash(&previous)] = previous;
+            }
+
+            let [red, green, blue, alpha] = previous.map(i32::from);
+            img.data.push(pixel::from_rgba(
+                &RGBAColor::new(red, green, blue, alpha),
+                255,
+            ));
+        }
+
+        return Ok(img);
+    }
+
+    // RGB, or RGBA for layouts with alpha, gray is written as RGB. Samples are rescaled to 8 bits.
+    pub fn export_to_qoi(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_qoi(writer));
+    }
+
+    pub fn write_qoi<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let channels = if P::has_alpha() { 4 } else { 3 };
+        let mut buffer = Vec::with_capacity(HEADER_SIZE + self.data.len() * 2 + END_MARKER.len());
+        buffer.extend_from_slice(SIGNATURE);
+        buffer.extend_from_slice(&(self.width as u32).to_be_bytes());
+        buffer.extend_from_slice(&(self.height as u32).to_be_bytes());
+        buffer.extend_from_slice(&[channels, 0]); // sRGB with linear alpha
+
+        let mut index = [[0_u8; 4]; 64];
+        let mut previous = [0, 0, 0, 255_u8];
+        let mut run = 0;
+        for (i, color) in self.data.iter().enumerate() {
+            let color = pixel::to_rgba(color, self.max_val as i32);
+            let current = [color.red, color.green, color.blue, color.alpha]
+                .map(|sample| codecs::rescale(sample, self.max_val, 255) as u8);
+
+            if current == previous {
+                run += 1;
+                if run == MAX_RUN || i + 1 == self.data.len() {
+                    buffer.push(OP_RUN | (run - 1) as u8);
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                buffer.push(OP_RUN | (run - 1) as u8);
+                run = 0;
+            }
+
+            let slot = hash(&current);
+            if index[slot] == current {
+                buffer.push(OP_INDEX | slot as u8);
+            } else if current[3] != previous[3] {
+                buffer.push(OP_RGBA);
+                buffer.extend_from_slice(&current);
+            } else {
+                let [red, green, blue] =
+                    [0, 1, 2].map(|c| current[c].wrapping_sub(previous[c]) as i8);
+                let (red_green, blue_green) = (red.wrapping_sub(green), blue.wrapping_sub(green));
+                if [red, green, blue]
+                    .iter()
+                    .all(|delta| (-2..=1).contains(delta))
+                {
+                    buffer.push(
+                        OP_DIFF
+                            | ((red + 2) as u8) << 4
+                            | ((green + 2) as u8) << 2
+                            | (blue + 2) as u8,
+                    );
+                } else if (-32..=31).contains(&green)
+                    && (-8..=7).contains(&red_green)
+                    && (-8..=7).contains(&blue_green)
+                {
+                    buffer.push(OP_LUMA | (green + 32) as u8);
+                    buffer.push(((red_green + 8) as u8) << 4 | (blue_green + 8) as u8);
+                } else {
+                    buffer.push(OP_RGB);
+                    buffer.extend_from_slice(&current[..3]);
+                }
+            }
+            index[slot] = current;
+            previous = current;
+        }
+
+        buffer.extend_from_slice(END_MARKER);
+        writer.write_all(&buffer)?;
+        return writer.flush();
+    }
+}
+
+fn hash(color: &[u8; 4]) -> usize {
+    let [red, green, blue, alpha] = color.map(usize::from);
+    return (red * 3 + green * 5 + blue * 7 + alpha * 11) % 64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures;
+    use crate::color::RGBColor;
+    use crate::pixel::{GrayAlphaColor, GrayColor};
+
+    fn round_trip<P: Pixel>(img: &PPMImage<P>) {
+        let mut bytes = Vec::new();
+        img.write_qoi(&mut bytes).unwrap();
+        let read = PPMImage::<P>::from_qoi_bytes(&bytes).unwrap();
+        assert_eq!((read.width, read.height), (img.width, img.height));
+        assert_eq!(read.data, img.data);
+    }
+
+    #[test]
+    fn every_layout_round_trips() {
+        round_trip(&fixtures::image::<GrayColor<i32>>(7, 3, 255));
+        round_trip(&fixtures::image::<GrayAlphaColor<i32>>(7, 3, 255));
+        round_trip(&fixtures::image::<RGBColor<i32>>(7, 3, 255));
+        round_trip(&fixtures::image::<RGBAColor<i32>>(7, 3, 255));
+    }
+
+    // long runs, repeated colors and small steps go through every op of the encoder
+    #[test]
+    fn runs_index_and_difference_ops_round_trip() {
+        let mut img = fixtures::image::<RGBAColor<i32>>(100, 2, 255);
+        for (i, color) in img.data.iter_mut().enumerate() {
+            *color = match i {
+                0..=69 => RGBAColor::new(10, 20, 30, 255),
+                70..=79 => RGBAColor::new(11 + i as i32 % 2, 20 + i as i32 % 9, 30, 255),
+                80..=89 => RGBAColor::new(10, 20, 30, 128 + i as i32 % 2),
+                _ => fixtures::pattern(i % 7, i / 7),
+            };
+        }
+        round_trip(&img);
+    }
+
+    #[test]
+    fn truncated_images_are_refused() {
+        let mut bytes = Vec::new();
+        fixtures::image::<RGBColor<i32>>(7, 3, 255)
+            .write_qoi(&mut bytes)
+            .unwrap();
+        bytes.truncate(bytes.len() - END_MARKER.len() - 1);
+        assert!(PPMImage::<RGBColor<i32>>::from_qoi_bytes(&bytes).is_err());
+    }
+}
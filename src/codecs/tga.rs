@@ -0,0 +1,257 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+
+use crate::codecs::{self, read_u16_le};
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, RGBAColor};
+
+// Last 18 bytes of a TGA 2.0 file, preceded by the extension and developer area offsets
+pub(crate) const FOOTER_SIGNATURE: &[u8] = b"TRUEVISION-XFILE.\0";
+const HEADER_SIZE: usize = 18;
+const TRUE_COLOR: u8 = 2;
+const GRAYSCALE: u8 = 3;
+const RLE_TRUE_COLOR: u8 = 10;
+const RLE_GRAYSCALE: u8 = 11;
+const TOP_TO_BOTTOM: u8 = 0x20;
+const RIGHT_TO_LEFT: u8 = 0x10;
+
+impl<P: Pixel> PPMImage<P> {
+    // 24 and 32 bit true color and 8 bit gray (16 with alpha) images, raw or run-length encoded,
+    // in any of the four origins. Color-mapped images are not supported.
+    pub fn from_tga(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::from_tga_bytes(&fs::read(file_path)?);
+    }
+
+    pub fn from_tga_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::decode_tga(bytes, &Limits::default());
+    }
+
+    pub(crate) fn decode_tga(bytes: &[u8], limits: &Limits) -> std::io::Result<PPMImage<P>> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated TGA header"));
+        }
+        let (id_length, image_type) = (bytes[0] as usize, bytes[2]);
+        let color_map_bytes = read_u16_le(bytes, 5)? as usize * (bytes[7] as usize).div_ceil(8);
+        let width = read_u16_le(bytes, 12)? as usize;
+        let height = read_u16_le(bytes, 14)? as usize;
+        let (depth, descriptor) = (bytes[16], bytes[17]);
+
+        let gray = match (image_type, depth) {
+            (TRUE_COLOR | RLE_TRUE_COLOR, 24 | 32) => false,
+            (GRAYSCALE | RLE_GRAYSCALE, 8 | 16) => true,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Unsupported TGA image type {} with {} bits per pixel",
+                        image_type, depth
+                    ),
+                ))
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid TGA dimensions"));
+        }
+        limits.check_image::<P>(width, height)?;
+
+        let pixel_bytes = depth as usize / 8;
+        let size = width * height * pixel_bytes;
+        let start = HEADER_SIZE + id_length + color_map_bytes;
+        let data = bytes
+            .get(start..)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Truncated TGA image"))?;
+        let raster = if image_type == RLE_TRUE_COLOR || image_type == RLE_GRAYSCALE {
+            run_length_decode(data, size, pixel_bytes)?
+        } else {
+            data.get(..size)
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Truncated TGA image"))?
+                .to_vec()
+        };
+
+        let mut img = PPMImage {
+            img_type: String::from(PPMImage::<P>::default_img_type()),
+            width,
+            height,
+            max_val: 255,
+            data: Vec::with_capacity(width * height),
+        };
+        for y in 0..height {
+            let row = if descriptor & TOP_TO_BOTTOM != 0 {
+                y
+            } else {
+                height - 1 - y
+            };
+            for x in 0..width {
+                let column = if descriptor & RIGHT_TO_LEFT != 0 {
+                    width - 1 - x
+                } else {
+                    x
+                };
+                let index = (row * width + column) * pixel_bytes;
+                let sample = |i: usize| raster[index + i] as i32;
+                // blue, green, red and alpha for true color, gray and alpha otherwise
+                let color = match (gray, pixel_bytes) {
+                    (true, 1) => RGBAColor::new(sample(0), sample(0), sample(0), 255),
+                    (true, _) => RGBAColor::new(sample(0), sample(0), sample(0), sample(1)),
+                    (false, 3) => RGBAColor::new(sample(2), sample(1), sample(0), 255),
+                    (false, _) => RGBAColor::new(sample(2), sample(1), sample(0), sample(3)),
+                };
+                img.data.push(pixel::from_rgba(&color, 255));
+            }
+        }
+
+        return Ok(img);
+    }
+
+    // Uncompressed, top to bottom, with a TGA 2.0 footer so the file can be recognized. Gray
+    // layouts are written as gray, samples are rescaled to 8 bits.
+    pub fn export_to_tga(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_tga(writer));
+    }
+
+    pub fn write_tga<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let (gray, alpha) = (P::COLOR_CHANNELS == 1, P::has_alpha());
+        let pixel_bytes = if gray { 1 } else { 3 } + alpha as usize;
+
+        let mut buffer = Vec::with_capacity(HEADER_SIZE + self.data.len() * pixel_bytes + 26);
+        buffer.extend_from_slice(&[0, 0, if gray { GRAYSCALE } else { TRUE_COLOR }]);
+        buffer.extend_from_slice(&[0; 5]); // no color map
+        buffer.extend_from_slice(&[0; 4]); // origin
+        buffer.extend_from_slice(&(self.width as u16).to_le_bytes());
+        buffer.extend_from_slice(&(self.height as u16).to_le_bytes());
+        buffer.push(pixel_bytes as u8 * 8);
+        buffer.push(TOP_TO_BOTTOM | if alpha { 8 } else { 0 });
+
+        let max_val = self.max_val as i32;
+        for color in &self.data {
+            let color = pixel::to_rgba(color, max_val);
+            let channels = if gray {
+                vec![color.red]
+            } else {
+                vec![color.blue, color.green, color.red]
+            };
+            for sample in channels {
+                buffer.push(codecs::rescale(sample, self.max_val, 255) as u8);
+            }
+            if alpha {
+                buffer.push(codecs::rescale(color.alpha, self.max_val, 255) as u8);
+            }
+        }
+
+        buffer.extend_from_slice(&[0; 8]); // no extension or developer area
+        buffer.extend_from_slice(FOOTER_SIGNATURE);
+        writer.write_all(&buffer)?;
+        return writer.flush();
+    }
+}
+
+// TGA 1.0 files have no signature, the header fields have to make sense instead
+pub(crate) fn plausible_header(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE {
+        return false;
+    }
+    let (color_map_type, image_type, depth) = (bytes[1], bytes[2], bytes[16]);
+    return color_map_type <= 1
+        && matches!(
+            (image_type, depth),
+            (TRUE_COLOR | RLE_TRUE_COLOR, 24 | 32) | (GRAYSCALE | RLE_GRAYSCALE, 8 | 16)
+        )
+        && bytes[12..16] != [0; 4];
+}
+
+// Packets of a count byte and either one pixel repeated (high bit set) or count raw pixels
+fn run_length_decode(data: &[u8], size: usize, pixel_bytes: usize) -> std::io::Result<Vec<u8>> {
+    let truncated = || Error::new(ErrorKind::UnexpectedEof, "Truncated TGA image");
+    // a packet of 1 + pixel_bytes bytes gives 128 pixels at most, the header may claim more
+    let mut result = Vec::with_capacity(size.min(data.len() * 128));
+    let mut position = 0;
+
+    while result.len() < size {
+        let packet = *data.get(position).ok_or_else(truncated)?;
+        let count = (packet & 0x7f) as usize + 1;
+        position += 1;
+        if packet & 0x80 != 0 {
+            let pixel = data
+                .get(position..position + pixel_bytes)
+                .ok_or_else(truncated)?;
+            for _ in 0..count {
+                result.extend_from_slice(pixel);
+            }
+            position += pixel_bytes;
+        } else {
+            let pixels = data
+                .get(position..position + count * pixel_bytes)
+                .ok_or_else(truncated)?;
+            result.extend_from_slice(pixels);
+            position += count * pixel_bytes;
+        }
+    }
+
+    result.truncate(size);
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures::{self, HEIGHT, WIDTH};
+    use crate::codecs::ImageFormat;
+    use crate::color::RGBColor;
+    use crate::pixel::{GrayAlphaColor, GrayColor};
+
+    fn round_trip<P: Pixel>() {
+        let img = fixtures::image::<P>(7, 3, 255);
+        let mut bytes = Vec::new();
+        img.write_tga(&mut bytes).unwrap();
+        assert_eq!(ImageFormat::detect(&bytes), Some(ImageFormat::Tga));
+        assert_eq!(
+            PPMImage::<P>::from_tga_bytes(&bytes).unwrap().data,
+            img.data
+        );
+    }
+
+    #[test]
+    fn every_layout_round_trips() {
+        round_trip::<GrayColor<i32>>();
+        round_trip::<GrayAlphaColor<i32>>();
+        round_trip::<RGBColor<i32>>();
+        round_trip::<RGBAColor<i32>>();
+    }
+
+    #[test]
+    fn run_length_encoded_bottom_up_images() {
+        // TGA 1.0 without a footer, alternating run and raw packets of one pixel
+        let bytes = fixtures::read("rle.tga");
+        assert_eq!(ImageFormat::detect(&bytes), Some(ImageFormat::Tga));
+        let img = PPMImage::<RGBColor<i32>>::from_tga_bytes(&bytes).unwrap();
+        assert_eq!((img.width, img.height), (WIDTH, HEIGHT));
+        for (i, color) in img.data.iter().enumerate() {
+            let expected = fixtures::pattern(i % WIDTH, i / WIDTH);
+            let expected = RGBColor::new(expected.red, expected.green, expected.blue);
+            assert_eq!(*color, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn gray_images_stored_right_to_left() {
+        let bytes = fixtures::read("right_to_left.tga");
+        let img = PPMImage::<GrayColor<i32>>::from_tga_bytes(&bytes).unwrap();
+        assert_eq!((img.width, img.height), (WIDTH, HEIGHT));
+        for (i, color) in img.data.iter().enumerate() {
+            assert_eq!(
+                color.gray,
+                fixtures::pattern(i % WIDTH, i / WIDTH).red,
+                "pixel {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_runs_are_refused() {
+        let bytes = fixtures::read("rle.tga");
+        let err = PPMImage::<RGBColor<i32>>::from_tga_bytes(&bytes[..bytes.len() - 2]);
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
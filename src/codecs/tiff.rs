@@ -0,0 +1,329 @@
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Seek, Write};
+
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{colortype, Compression, TiffEncoder};
+use tiff::{ColorType, TiffError};
+
+use crate::codecs;
+use crate::image::PPMImage;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, RGBAColor};
+
+impl<P: Pixel> PPMImage<P> {
+    // First page of the file, see `decode_pages`
+    pub fn from_tiff(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::from_tiff_bytes(&fs::read(file_path)?);
+    }
+
+    pub fn from_tiff_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::decode_tiff(bytes, &Limits::default());
+    }
+
+    pub(crate) fn decode_tiff(bytes: &[u8], limits: &Limits) -> std::io::Result<PPMImage<P>> {
+        return decode_page(&mut decoder(bytes, limits)?, limits);
+    }
+
+    // Single page, LZW compressed, see `save_pages`
+    pub fn export_to_tiff(&self, file_path: &str) -> std::io::Result<()> {
+        return save_pages(std::slice::from_ref(self), file_path);
+    }
+
+    pub fn write_tiff<W: Write + Seek>(&self, writer: W) -> std::io::Result<()> {
+        return write_pages(std::slice::from_ref(self), writer);
+    }
+}
+
+pub fn load_pages<P: Pixel>(file_path: &str) -> std::io::Result<Vec<PPMImage<P>>> {
+    return decode_pages(&fs::read(file_path)?);
+}
+
+// Every page of a TIFF file: uncompressed, LZW or Deflate, striped or tiled, chunky or planar,
+// 8 or 16 bit gray, gray + alpha, RGB and RGBA. Pages are converted to `P` like `from_netpbm`
+// does.
+pub fn decode_pages<P: Pixel>(bytes: &[u8]) -> std::io::Result<Vec<PPMImage<P>>> {
+    return decode_pages_with_limits(bytes, &Limits::default());
+}
+
+// `limits.max_bytes` goes for all the pages together, pages can share their strips so even a
+// small file can hold many large ones
+pub fn decode_pages_with_limits<P: Pixel>(
+    bytes: &[u8],
+    limits: &Limits,
+) -> std::io::Result<Vec<PPMImage<P>>> {
+    let mut limits = *limits;
+    let mut decoder = decoder(bytes, &limits)?;
+    let mut pages = Vec::new();
+    loop {
+        let page = decode_page(&mut decoder, &limits)?;
+        limits.max_bytes -= page.data.len() * std::mem::size_of::<P>();
+        pages.push(page);
+        if !decoder.more_images() {
+            return Ok(pages);
+        }
+        decoder.next_image().map_err(decoding_error)?;
+    }
+}
+
+fn decoder<'a>(bytes: &'a [u8], limits: &Limits) -> std::io::Result<Decoder<Cursor<&'a [u8]>>> {
+    let mut decoder_limits = tiff::decoder::Limits::default();
+    decoder_limits.decoding_buffer_size = limits.max_bytes;
+    return Ok(Decoder::new(Cursor::new(bytes))
+        .map_err(decoding_error)?
+        .with_limits(decoder_limits));
+}
+
+// One page per image, 8 bit for a max_val up to 255 and 16 bit above. Gray + alpha layouts are
+// written as RGBA, the encoder has no such color type.
+pub fn save_pages<P: Pixel>(pages: &[PPMImage<P>], file_path: &str) -> std::io::Result<()> {
+    return codecs::write_atomically(file_path, |writer| write_pages(pages, writer));
+}
+
+pub fn write_pages<P: Pixel, W: Write + Seek>(
+    pages: &[PPMImage<P>],
+    writer: W,
+) -> std::io::Result<()> {
+    let mut encoder = TiffEncoder::new(writer)
+        .map_err(encoding_error)?
+        .with_compression(Compression::Lzw);
+
+    for page in pages {
+        let (width, height) = (page.width as u32, page.height as u32);
+        let channels = if P::COLOR_CHANNELS == 1 && !P::has_alpha() {
+            1
+        } else {
+            3 + P::has_alpha() as usize
+        };
+        let wide = page.max_val > 255;
+        let target = if wide { 65535 } else { 255 };
+
+        let mut samples = Vec::with_capacity(page.data.len() * channels);
+        for color in &page.data {
+            let color = pixel::to_rgba(color, page.max_val as i32);
+            let channels = match channels {
+                1 => vec![color.red],
+                3 => vec![color.red, color.green, color.blue],
+                _ => vec![color.red, color.green, color.blue, color.alpha],
+            };
+            for sample in channels {
+                samples.push(codecs::rescale(sample, page.max_val, target) as u16);
+            }
+        }
+
+        let result = if wide {
+            match channels {
+                1 => encoder.write_image::<colortype::Gray16>(width, height, &samples),
+                3 => encoder.write_image::<colortype::RGB16>(width, height, &samples),
+                _ => encoder.write_image::<colortype::RGBA16>(width, height, &samples),
+            }
+        } else {
+            let samples: Vec<u8> = samples.iter().map(|&sample| sample as u8).collect();
+            match channels {
+                1 => encoder.write_image::<colortype::Gray8>(width, height, &samples),
+                3 => encoder.write_image::<colortype::RGB8>(width, height, &samples),
+                _ => encoder.write_image::<colortype::RGBA8>(width, height, &samples),
+            }
+        };
+        result.map_err(encoding_error)?;
+    }
+
+    return Ok(());
+}
+
+fn decode_page<P: Pixel>(
+    decoder: &mut Decoder<Cursor<&[u8]>>,
+    limits: &Limits,
+) -> std::io::Result<PPMImage<P>> {
+    let (width, height) = decoder.dimensions().map_err(decoding_error)?;
+    let (width, height) = (width as usize, height as usize);
+    let pixels = limits.check_image::<P>(width, height)?;
+    let color_type = decoder.colortype().map_err(decoding_error)?;
+    let channels = match color_type {
+        ColorType::Gray(8 | 16) => 1,
+        ColorType::GrayA(8 | 16) => 2,
+        ColorType::Multiband {
+            bit_depth: 8 | 16,
+            num_samples: 2,
+        } => 2,
+        ColorType::RGB(8 | 16) => 3,
+        ColorType::RGBA(8 | 16) => 4,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported TIFF color type {:?}", color_type),
+            ))
+        }
+    };
+
+    let mut result = DecodingResult::U8(Vec::new());
+    let layout = decoder
+        .read_image_to_buffer(&mut result)
+        .map_err(decoding_error)?;
+    let (samples, max_val): (Vec<i32>, i32) = match result {
+        DecodingResult::U8(samples) => (samples.into_iter().map(i32::from).collect(), 255),
+        DecodingResult::U16(samples) => (samples.into_iter().map(i32::from).collect(), 65535),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unsupported TIFF sample format",
+            ))
+        }
+    };
+    if samples.len() < pixels * channels {
+        return Err(Error::new(ErrorKind::InvalidData, "Truncated TIFF image"));
+    }
+    // planar images store each channel as a whole plane
+    let planar = layout.planes > 1;
+    let sample = |index: usize, c: usize| {
+        if planar {
+            samples[c * pixels + index]
+        } else {
+            samples[index * channels + c]
+        }
+    };
+
+    let mut img = PPMImage {
+        img_type: String::from(PPMImage::<P>::default_img_type()),
+        width,
+        height,
+        max_val: pixel::stored_max_val::<P>(max_val as usize),
+        data: Vec::with_capacity(pixels),
+    };
+    for index in 0..pixels {
+        let color = match channels {
+            1 => RGBAColor::new(
+                sample(index, 0),
+                sample(index, 0),
+                sample(index, 0),
+                max_val,
+            ),
+            2 => {
+                let gray = sample(index, 0);
+                RGBAColor::new(gray, gray, gray, sample(index, 1))
+            }
+            3 => RGBAColor::new(
+                sample(index, 0),
+                sample(index, 1),
+                sample(index, 2),
+                max_val,
+            ),
+            _ => RGBAColor::new(
+                sample(index, 0),
+                sample(index, 1),
+                sample(index, 2),
+                sample(index, 3),
+            ),
+        };
+        img.data.push(pixel::from_rgba(&color, max_val as usize));
+    }
+
+    return Ok(img);
+}
+
+fn decoding_error(err: TiffError) -> Error {
+    return match err {
+        TiffError::IoError(err) => err,
+        err => Error::new(ErrorKind::InvalidData, err),
+    };
+}
+
+fn encoding_error(err: TiffError) -> Error {
+    return match err {
+        TiffError::IoError(err) => err,
+        err => Error::new(ErrorKind::InvalidInput, err),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::{embed_in_pages, extract_from_pages};
+    use crate::codecs::fixtures::{self, HEIGHT, WIDTH};
+    use crate::color::RGBColor;
+    use crate::lsb::LSBStegano;
+    use crate::pixel::{GrayAlphaColor, GrayColor};
+
+    fn round_trip<P: Pixel>(max_val: usize) {
+        let img = fixtures::image::<P>(7, 3, max_val);
+        let mut bytes = Cursor::new(Vec::new());
+        img.write_tiff(&mut bytes).unwrap();
+        let read = PPMImage::<P>::from_tiff_bytes(bytes.get_ref()).unwrap();
+        assert_eq!((read.width, read.height), (7, 3));
+        assert_eq!(read.max_val, max_val);
+        assert_eq!(read.data, img.data);
+    }
+
+    fn assert_pattern(img: &PPMImage, scale: i32) {
+        assert_eq!((img.width, img.height), (WIDTH, HEIGHT));
+        for (i, color) in img.data.iter().enumerate() {
+            let expected = fixtures::pattern(i % WIDTH, i / WIDTH);
+            let expected = RGBColor::new(expected.red, expected.green, expected.blue);
+            assert_eq!(*color, expected.map_channels(|c| c * scale), "pixel {}", i);
+        }
+    }
+
+    fn assert_same_pages(read: &[PPMImage], pages: &[PPMImage]) {
+        assert_eq!(read.len(), pages.len());
+        for (read, page) in read.iter().zip(pages) {
+            assert_eq!((read.width, read.height), (page.width, page.height));
+            assert_eq!(read.data, page.data);
+        }
+    }
+
+    #[test]
+    fn every_layout_round_trips_on_8_and_16_bits() {
+        for max_val in [255, 65535] {
+            round_trip::<GrayColor<i32>>(max_val);
+            round_trip::<GrayAlphaColor<i32>>(max_val);
+            round_trip::<RGBColor<i32>>(max_val);
+            round_trip::<RGBAColor<i32>>(max_val);
+        }
+    }
+
+    #[test]
+    fn deflate_compressed_images() {
+        let img = PPMImage::from_tiff_bytes(&fixtures::read("deflate.tif")).unwrap();
+        assert_eq!(img.max_val, 255);
+        assert_pattern(&img, 1);
+    }
+
+    #[test]
+    fn planar_16_bit_images() {
+        let img = PPMImage::from_tiff_bytes(&fixtures::read("planar16.tif")).unwrap();
+        assert_eq!(img.max_val, 65535);
+        assert_pattern(&img, 257);
+    }
+
+    #[test]
+    fn every_page_is_written_and_read_back() {
+        let pages = vec![
+            fixtures::image::<RGBColor<i32>>(7, 3, 255),
+            fixtures::image::<RGBColor<i32>>(4, 9, 255),
+            fixtures::image::<RGBColor<i32>>(1, 1, 255),
+        ];
+        let mut bytes = Cursor::new(Vec::new());
+        write_pages(&pages, &mut bytes).unwrap();
+        assert_same_pages(&decode_pages(bytes.get_ref()).unwrap(), &pages);
+    }
+
+    #[test]
+    fn secrets_hidden_across_pages_survive_the_file() {
+        let pages: Vec<PPMImage> = (0..3)
+            .map(|_| fixtures::image::<RGBColor<i32>>(16, 16, 255))
+            .collect();
+        let secret = fixtures::image::<RGBColor<i32>>(8, 12, 255);
+        let algorithm = LSBStegano::new(2, None).unwrap();
+        let (stego_pages, keys) = embed_in_pages(&algorithm, &pages, &secret).unwrap();
+        // the secret does not fit on one page
+        assert!(keys.len() > 1);
+
+        let mut bytes = Cursor::new(Vec::new());
+        write_pages(&stego_pages, &mut bytes).unwrap();
+        let read = decode_pages(bytes.get_ref()).unwrap();
+        assert_same_pages(&read, &stego_pages);
+        assert_eq!(
+            extract_from_pages(&algorithm, &read, &keys).unwrap().data,
+            secret.data
+        );
+    }
+}
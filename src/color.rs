@@ -1,4 +1,6 @@
-#[derive(Debug, Default, Copy, Clone)]
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct RGBColor<T> {
     pub red: T,
     pub green: T,
@@ -10,36 +12,395 @@ impl<T: Copy> RGBColor<T> {
         return RGBColor { red, green, blue };
     }
 
-    pub fn add(self, color: &RGBColor<T>) -> RGBColor<T>
-    where
-        T: std::ops::Add<Output = T>,
-    {
+    pub fn map<U, F: Fn(T) -> U>(self, f: F) -> RGBColor<U> {
         return RGBColor {
-            red: self.red + color.red,
-            green: self.green + color.green,
-            blue: self.blue + color.blue,
+            red: f(self.red),
+            green: f(self.green),
+            blue: f(self.blue),
         };
     }
 
-    pub fn sub(self, color: &RGBColor<T>) -> RGBColor<T>
+    // Same as `as` on every channel: floats are truncated, integers wrap
+    pub fn cast<U: CastFrom<T>>(self) -> RGBColor<U> {
+        return self.map(U::cast_from);
+    }
+
+    // Floats are rounded, then every channel is clamped to the range of `U`
+    pub fn saturating_cast<U: SaturateFrom<T>>(self) -> RGBColor<U> {
+        return self.map(U::saturate_from);
+    }
+
+    pub fn clamp(self, min: T, max: T) -> RGBColor<T>
     where
-        T: std::ops::Sub<Output = T>,
+        T: PartialOrd,
     {
+        return self.map(|value| {
+            if value < min {
+                min
+            } else if value > max {
+                max
+            } else {
+                value
+            }
+        });
+    }
+}
+
+// `value as Self` between sample types
+pub trait CastFrom<T> {
+    fn cast_from(value: T) -> Self;
+}
+
+// Conversion between sample types that rounds floats and clamps to the range of `Self`
+pub trait SaturateFrom<T> {
+    fn saturate_from(value: T) -> Self;
+}
+
+macro_rules! impl_cast_from {
+    ($($from:ty => $($to:ty),+);+) => {
+        $($(
+            impl CastFrom<$from> for $to {
+                fn cast_from(value: $from) -> $to {
+                    return value as $to;
+                }
+            }
+        )+)+
+    };
+}
+
+impl_cast_from!(u8 => u8, i32, f64; i32 => u8, i32, f64; f64 => u8, i32, f64);
+
+macro_rules! impl_saturate_from {
+    (integer: $($from:ty => $($to:ty),+);+) => {
+        $($(
+            impl SaturateFrom<$from> for $to {
+                fn saturate_from(value: $from) -> $to {
+                    return (value as i64).clamp(<$to>::MIN as i64, <$to>::MAX as i64) as $to;
+                }
+            }
+        )+)+
+    };
+    // `as` from a float already saturates (and maps NaN to 0)
+    (float: $($from:ty => $($to:ty),+);+) => {
+        $($(
+            impl SaturateFrom<$from> for $to {
+                fn saturate_from(value: $from) -> $to {
+                    return value.round() as $to;
+                }
+            }
+        )+)+
+    };
+    (to float: $($from:ty => $($to:ty),+);+) => {
+        $($(
+            impl SaturateFrom<$from> for $to {
+                fn saturate_from(value: $from) -> $to {
+                    return value as $to;
+                }
+            }
+        )+)+
+    };
+}
+
+impl_saturate_from!(integer: u8 => u8, i32; i32 => u8, i32);
+impl_saturate_from!(float: f64 => u8, i32);
+impl_saturate_from!(to float: u8 => f64; i32 => f64; f64 => f64);
+
+impl From<RGBColor<u8>> for RGBColor<i32> {
+    fn from(color: RGBColor<u8>) -> Self {
+        return color.cast();
+    }
+}
+
+impl From<RGBColor<u8>> for RGBColor<f64> {
+    fn from(color: RGBColor<u8>) -> Self {
+        return color.cast();
+    }
+}
+
+impl From<RGBColor<i32>> for RGBColor<f64> {
+    fn from(color: RGBColor<i32>) -> Self {
+        return color.cast();
+    }
+}
+
+// Channel by channel with another color, or with the same scalar on every channel
+macro_rules! impl_color_op {
+    ($op:ident, $method:ident, $op_assign:ident, $method_assign:ident) => {
+        impl<T: $op<Output = T>> $op for RGBColor<T> {
+            type Output = RGBColor<T>;
+
+            fn $method(self, color: RGBColor<T>) -> RGBColor<T> {
+                return RGBColor {
+                    red: self.red.$method(color.red),
+                    green: self.green.$method(color.green),
+                    blue: self.blue.$method(color.blue),
+                };
+            }
+        }
+
+        impl<T: $op<Output = T> + Copy> $op<T> for RGBColor<T> {
+            type Output = RGBColor<T>;
+
+            fn $method(self, number: T) -> RGBColor<T> {
+                return RGBColor {
+                    red: self.red.$method(number),
+                    green: self.green.$method(number),
+                    blue: self.blue.$method(number),
+                };
+            }
+        }
+
+        impl<T: $op_assign> $op_assign for RGBColor<T> {
+            fn $method_assign(&mut self, color: RGBColor<T>) {
+                self.red.$method_assign(color.red);
+                self.green.$method_assign(color.green);
+                self.blue.$method_assign(color.blue);
+            }
+        }
+
+        impl<T: $op_assign + Copy> $op_assign<T> for RGBColor<T> {
+            fn $method_assign(&mut self, number: T) {
+                self.red.$method_assign(number);
+                self.green.$method_assign(number);
+                self.blue.$method_assign(number);
+            }
+        }
+    };
+}
+
+impl_color_op!(Add, add, AddAssign, add_assign);
+impl_color_op!(Sub, sub, SubAssign, sub_assign);
+impl_color_op!(Mul, mul, MulAssign, mul_assign);
+impl_color_op!(Div, div, DivAssign, div_assign);
+
+impl<T: Neg<Output = T>> Neg for RGBColor<T> {
+    type Output = RGBColor<T>;
+
+    fn neg(self) -> RGBColor<T> {
         return RGBColor {
-            red: self.red - color.red,
-            green: self.green - color.green,
-            blue: self.blue - color.blue,
+            red: -self.red,
+            green: -self.green,
+            blue: -self.blue,
         };
     }
+}
 
-    pub fn div_by(self, number: T) -> RGBColor<T>
-    where
-        T: std::ops::Div<Output = T>,
-    {
-        return RGBColor {
-            red: self.red / number,
-            green: self.green / number,
-            blue: self.blue / number,
+// Spaces the DWT can run in. Converted colors are stored in an RGBColor with the channels in
+// order (Y, Cb, Cr), (Y, U, V), (H, S, V) or (L, a, b).
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    RGB,
+    YCbCr601,
+    YCbCr709,
+    YUV,
+    HSV,
+    Lab,
+}
+
+impl ColorSpace {
+    // RGB in 0..=1 to this space with every channel scaled to roughly 0..=1
+    pub fn from_rgb(&self, color: RGBColor<f64>) -> RGBColor<f64> {
+        return match self {
+            ColorSpace::RGB => color,
+            ColorSpace::YCbCr601 => color.to_ycbcr_bt601(),
+            ColorSpace::YCbCr709 => color.to_ycbcr_bt709(),
+            ColorSpace::YUV => {
+                let yuv = color.to_yuv();
+                RGBColor::new(
+                    yuv.red,
+                    yuv.green / (2.0 * U_MAX) + 0.5,
+                    yuv.blue / (2.0 * V_MAX) + 0.5,
+                )
+            }
+            ColorSpace::HSV => {
+                let hsv = color.to_hsv();
+                RGBColor::new(hsv.red / 360.0, hsv.green, hsv.blue)
+            }
+            ColorSpace::Lab => {
+                let lab = color.to_lab();
+                RGBColor::new(
+                    lab.red / 100.0,
+                    (lab.green + 128.0) / 255.0,
+                    (lab.blue + 128.0) / 255.0,
+                )
+            }
+        };
+    }
+
+    pub fn to_rgb(&self, color: RGBColor<f64>) -> RGBColor<f64> {
+        return match self {
+            ColorSpace::RGB => color,
+            ColorSpace::YCbCr601 => RGBColor::from_ycbcr_bt601(color),
+            ColorSpace::YCbCr709 => RGBColor::from_ycbcr_bt709(color),
+            ColorSpace::YUV => RGBColor::from_yuv(RGBColor::new(
+                color.red,
+                (color.green - 0.5) * 2.0 * U_MAX,
+                (color.blue - 0.5) * 2.0 * V_MAX,
+            )),
+            ColorSpace::HSV => {
+                RGBColor::from_hsv(RGBColor::new(color.red * 360.0, color.green, color.blue))
+            }
+            ColorSpace::Lab => RGBColor::from_lab(RGBColor::new(
+                color.red * 100.0,
+                color.green * 255.0 - 128.0,
+                color.blue * 255.0 - 128.0,
+            )),
+        };
+    }
+}
+
+const U_MAX: f64 = 0.436;
+const V_MAX: f64 = 0.615;
+// D65 reference white
+const WHITE_X: f64 = 0.95047;
+const WHITE_Y: f64 = 1.0;
+const WHITE_Z: f64 = 1.08883;
+
+// Conversions take and give RGB in 0..=1
+impl RGBColor<f64> {
+    // Full range, Cb and Cr centered on 0.5
+    pub fn to_ycbcr_bt601(self) -> RGBColor<f64> {
+        return self.to_ycbcr(0.299, 0.114);
+    }
+
+    pub fn from_ycbcr_bt601(color: RGBColor<f64>) -> RGBColor<f64> {
+        return RGBColor::from_ycbcr(color, 0.299, 0.114);
+    }
+
+    pub fn to_ycbcr_bt709(self) -> RGBColor<f64> {
+        return self.to_ycbcr(0.2126, 0.0722);
+    }
+
+    pub fn from_ycbcr_bt709(color: RGBColor<f64>) -> RGBColor<f64> {
+        return RGBColor::from_ycbcr(color, 0.2126, 0.0722);
+    }
+
+    fn to_ycbcr(self, kr: f64, kb: f64) -> RGBColor<f64> {
+        let y = kr * self.red + (1.0 - kr - kb) * self.green + kb * self.blue;
+        return RGBColor::new(
+            y,
+            (self.blue - y) / (2.0 * (1.0 - kb)) + 0.5,
+            (self.red - y) / (2.0 * (1.0 - kr)) + 0.5,
+        );
+    }
+
+    fn from_ycbcr(color: RGBColor<f64>, kr: f64, kb: f64) -> RGBColor<f64> {
+        let red = color.red + 2.0 * (1.0 - kr) * (color.blue - 0.5);
+        let blue = color.red + 2.0 * (1.0 - kb) * (color.green - 0.5);
+        let green = (color.red - kr * red - kb * blue) / (1.0 - kr - kb);
+        return RGBColor::new(red, green, blue);
+    }
+
+    // Analog BT.601 YUV, U in +/-0.436 and V in +/-0.615
+    pub fn to_yuv(self) -> RGBColor<f64> {
+        let y = 0.299 * self.red + 0.587 * self.green + 0.114 * self.blue;
+        return RGBColor::new(y, 0.492 * (self.blue - y), 0.877 * (self.red - y));
+    }
+
+    pub fn from_yuv(color: RGBColor<f64>) -> RGBColor<f64> {
+        let blue = color.red + color.green / 0.492;
+        let red = color.red + color.blue / 0.877;
+        let green = (color.red - 0.299 * red - 0.114 * blue) / 0.587;
+        return RGBColor::new(red, green, blue);
+    }
+
+    // Hue in degrees, saturation and value in 0..=1
+    pub fn to_hsv(self) -> RGBColor<f64> {
+        let max = self.red.max(self.green).max(self.blue);
+        let min = self.red.min(self.green).min(self.blue);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == self.red {
+            60.0 * ((self.green - self.blue) / delta).rem_euclid(6.0)
+        } else if max == self.green {
+            60.0 * ((self.blue - self.red) / delta + 2.0)
+        } else {
+            60.0 * ((self.red - self.green) / delta + 4.0)
         };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+        return RGBColor::new(hue, saturation, max);
+    }
+
+    pub fn from_hsv(color: RGBColor<f64>) -> RGBColor<f64> {
+        let chroma = color.blue * color.green;
+        let sector = color.red.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+        let m = color.blue - chroma;
+
+        let (red, green, blue) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        return RGBColor::new(red + m, green + m, blue + m);
+    }
+
+    // CIELAB from sRGB with a D65 white point, L in 0..=100
+    pub fn to_lab(self) -> RGBColor<f64> {
+        let (r, g, b) = (
+            srgb_to_linear(self.red),
+            srgb_to_linear(self.green),
+            srgb_to_linear(self.blue),
+        );
+        let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = 0.0193339 * r + 0.1191920 * g + 0.9503041 * b;
+
+        let (fx, fy, fz) = (lab_f(x / WHITE_X), lab_f(y / WHITE_Y), lab_f(z / WHITE_Z));
+        return RGBColor::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz));
+    }
+
+    pub fn from_lab(color: RGBColor<f64>) -> RGBColor<f64> {
+        let fy = (color.red + 16.0) / 116.0;
+        let fx = fy + color.green / 500.0;
+        let fz = fy - color.blue / 200.0;
+        let (x, y, z) = (
+            WHITE_X * lab_f_inverse(fx),
+            WHITE_Y * lab_f_inverse(fy),
+            WHITE_Z * lab_f_inverse(fz),
+        );
+
+        return RGBColor::new(
+            linear_to_srgb(3.2404542 * x - 1.5371385 * y - 0.4985314 * z),
+            linear_to_srgb(-0.9692660 * x + 1.8760108 * y + 0.0415560 * z),
+            linear_to_srgb(0.0556434 * x - 0.2040259 * y + 1.0572252 * z),
+        );
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        return value / 12.92;
+    }
+    return ((value + 0.055) / 1.055).powf(2.4);
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        return 12.92 * value;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        return t.cbrt();
+    }
+    return t / (3.0 * DELTA * DELTA) + 4.0 / 29.0;
+}
+
+fn lab_f_inverse(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA {
+        return t * t * t;
     }
+    return 3.0 * DELTA * DELTA * (t - 4.0 / 29.0);
 }
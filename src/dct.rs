@@ -0,0 +1,429 @@
+use std::io::{Error, ErrorKind};
+
+use crate::algorithm::{self, StegoAlgorithm, StegoKey, BITS_PER_SECRET_PIXEL};
+use crate::color::RGBColor;
+use crate::image::PPMImage;
+use crate::random::SplitMix64;
+
+const N: usize = 8;
+// Attempts at pulling a block back onto its target coefficients after rounding and clipping
+const MAX_PROJECTIONS: usize = 8;
+
+// 8x8 block DCT of the luminance with F5 style embedding: mid frequency coefficients are
+// quantized with `step`, a bit is the parity of a non zero coefficient (inverted when negative)
+// and is changed by decreasing its magnitude. Matrix encoding hides `matrix_k` bits in groups of
+// 2^k - 1 coefficients with at most one change per group.
+// The luminance change is added to R, G and B alike, which leaves the chrominance untouched.
+#[derive(Debug, Clone)]
+pub struct DCTStegano {
+    step: f64,
+    matrix_k: u32,
+    password: Option<String>,
+}
+
+impl Default for DCTStegano {
+    fn default() -> Self {
+        return DCTStegano {
+            step: 10.0,
+            matrix_k: 2,
+            password: None,
+        };
+    }
+}
+
+impl DCTStegano {
+    pub fn new(step: f64, matrix_k: u32, password: Option<String>) -> std::io::Result<DCTStegano> {
+        if !(step >= 2.0 && step.is_finite()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("DCT quantization step must be at least 2, got {}", step),
+            ));
+        }
+        if !(1..=7).contains(&matrix_k) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Matrix encoding uses 1 to 7 bits per group, got {}",
+                    matrix_k
+                ),
+            ));
+        }
+        return Ok(DCTStegano {
+            step,
+            matrix_k,
+            password,
+        });
+    }
+
+    fn group_size(&self) -> usize {
+        return (1 << self.matrix_k) - 1;
+    }
+
+    // (block, index in mid_band()) in the order coefficients are visited
+    fn positions(&self, blocks: usize) -> Vec<(usize, usize)> {
+        let mid_band = mid_band();
+        let mut positions = Vec::with_capacity(blocks * mid_band.len());
+        for block in 0..blocks {
+            for index in 0..mid_band.len() {
+                positions.push((block, index));
+            }
+        }
+        if let Some(password) = &self.password {
+            SplitMix64::from_password(password).shuffle(&mut positions);
+        }
+        return positions;
+    }
+
+    fn quantized_blocks(&self, img: &PPMImage) -> Vec<Vec<i32>> {
+        let mid_band = mid_band();
+        let (blocks_width, blocks_height) = (img.width / N, img.height / N);
+        let mut result = Vec::with_capacity(blocks_width * blocks_height);
+
+        for by in 0..blocks_height {
+            for bx in 0..blocks_width {
+                let coefficients = forward_dct(&luminance_of_block(img, bx, by));
+                result.push(
+                    mid_band
+                        .iter()
+                        .map(|&index| (coefficients[index] / self.step).round() as i32)
+                        .collect(),
+                );
+            }
+        }
+
+        return result;
+    }
+
+    // Moves the luminance of a block until its mid band quantizes to `targets`
+    fn project_block(&self, img: &mut PPMImage, bx: usize, by: usize, targets: &[i32]) -> bool {
+        let mid_band = mid_band();
+        let max_val = img.max_val as i32;
+
+        for _ in 0..MAX_PROJECTIONS {
+            let current = luminance_of_block(img, bx, by);
+            let mut coefficients = forward_dct(&current);
+            let mut done = true;
+            for (i, &index) in mid_band.iter().enumerate() {
+                if (coefficients[index] / self.step).round() as i32 != targets[i] {
+                    done = false;
+                }
+                coefficients[index] = targets[i] as f64 * self.step;
+            }
+            if done {
+                return true;
+            }
+
+            let desired = inverse_dct(&coefficients);
+            for y in 0..N {
+                for x in 0..N {
+                    let delta = (desired[y * N + x] - current[y * N + x]).round() as i32;
+                    let pixel = &mut img.data[(by * N + y) * img.width + bx * N + x];
+                    *pixel = (*pixel + delta).clamp(0, max_val);
+                }
+            }
+        }
+
+        let coefficients = forward_dct(&luminance_of_block(img, bx, by));
+        return mid_band
+            .iter()
+            .enumerate()
+            .all(|(i, &index)| (coefficients[index] / self.step).round() as i32 == targets[i]);
+    }
+}
+
+impl StegoAlgorithm for DCTStegano {
+    fn embed(&self, cover: &PPMImage, secret: &PPMImage) -> std::io::Result<(PPMImage, StegoKey)> {
+        let payload = algorithm::secret_to_bytes(secret);
+        let total_bits = payload.len() * 8;
+        let mut targets = self.quantized_blocks(cover);
+        let positions = self.positions(targets.len());
+        let (k, n) = (self.matrix_k as usize, self.group_size());
+        let (mut cursor, mut bit_index) = (0, 0);
+
+        while bit_index < total_bits {
+            let mut message = 0;
+            for i in 0..k {
+                if bit_index + i < total_bits {
+                    message |= (algorithm::bit_of(&payload, bit_index + i) as usize) << i;
+                }
+            }
+
+            // A change that shrinks a coefficient to zero removes it, the group is then rebuilt
+            // from the same starting point
+            loop {
+                let mut group = Vec::with_capacity(n);
+                let mut end = cursor;
+                while group.len() < n && end < positions.len() {
+                    let (block, index) = positions[end];
+                    if targets[block][index] != 0 {
+                        group.push(positions[end]);
+                    }
+                    end += 1;
+                }
+                if group.len() < n {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Secret image is too large for this cover",
+                    ));
+                }
+
+                let syndrome = group_hash(&group, &targets) ^ message;
+                if syndrome == 0 {
+                    cursor = end;
+                    break;
+                }
+                let (block, index) = group[syndrome - 1];
+                let coefficient = &mut targets[block][index];
+                *coefficient -= coefficient.signum();
+                if *coefficient != 0 {
+                    cursor = end;
+                    break;
+                }
+            }
+            bit_index += k;
+        }
+
+        let mut stego = PPMImage {
+            img_type: String::from("P6"),
+            width: cover.width,
+            height: cover.height,
+            max_val: cover.max_val,
+            data: cover.data.clone(),
+        };
+        let blocks_width = cover.width / N;
+        for (block, block_targets) in targets.iter().enumerate() {
+            if !self.project_block(
+                &mut stego,
+                block % blocks_width,
+                block / blocks_width,
+                block_targets,
+            ) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Cover is too saturated for this quantization step, try a larger one",
+                ));
+            }
+        }
+
+        return Ok((stego, StegoKey::new(secret.width, secret.height)));
+    }
+
+    fn extract(&self, stego: &PPMImage, key: &StegoKey) -> std::io::Result<PPMImage> {
+        // at most one bit per coefficient, below one secret pixel per cover pixel
+        let pixels = algorithm::secret_pixels(key, stego.data.len())?;
+        let mut payload = vec![0_u8; pixels * 3];
+        let total_bits = payload.len() * 8;
+        let coefficients = self.quantized_blocks(stego);
+        let positions = self.positions(coefficients.len());
+        let (k, n) = (self.matrix_k as usize, self.group_size());
+        let (mut cursor, mut bit_index) = (0, 0);
+
+        while bit_index < total_bits {
+            let mut group = Vec::with_capacity(n);
+            while group.len() < n && cursor < positions.len() {
+                let (block, index) = positions[cursor];
+                if coefficients[block][index] != 0 {
+                    group.push(positions[cursor]);
+                }
+                cursor += 1;
+            }
+            if group.len() < n {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Stego key asks for more pixels than the image can carry",
+                ));
+            }
+
+            let message = group_hash(&group, &coefficients);
+            for i in 0..k {
+                if bit_index + i < total_bits {
+                    algorithm::set_bit(&mut payload, bit_index + i, (message >> i) as u8);
+                }
+            }
+            bit_index += k;
+        }
+
+        return Ok(algorithm::secret_from_bytes(
+            key.width, key.height, &payload,
+        ));
+    }
+
+    // Every group of the matrix encoding changes at most one coefficient without removing it,
+    // the other changes shrink a +/-1 coefficient to zero. Even if all of them are removed there
+    // are enough groups left for what this returns.
+    fn capacity(&self, cover: &PPMImage) -> usize {
+        let (mut non_zero, mut ones) = (0, 0);
+        for block in self.quantized_blocks(cover) {
+            for coefficient in block {
+                if coefficient != 0 {
+                    non_zero += 1;
+                }
+                if coefficient.abs() == 1 {
+                    ones += 1;
+                }
+            }
+        }
+
+        let bits = (non_zero - ones) / self.group_size() * self.matrix_k as usize;
+        return bits / BITS_PER_SECRET_PIXEL;
+    }
+}
+
+fn coefficient_bit(coefficient: i32) -> usize {
+    if coefficient > 0 {
+        return (coefficient & 1) as usize;
+    }
+    return 1 - (coefficient.abs() & 1) as usize;
+}
+
+fn group_hash(group: &[(usize, usize)], coefficients: &[Vec<i32>]) -> usize {
+    let mut hash = 0;
+    for (i, &(block, index)) in group.iter().enumerate() {
+        if coefficient_bit(coefficients[block][index]) == 1 {
+            hash ^= i + 1;
+        }
+    }
+    return hash;
+}
+
+// Coefficients with 3 <= u + v <= 6, between the DC/low frequencies carrying most of the
+// energy and the high frequencies that do not survive any processing
+fn mid_band() -> Vec<usize> {
+    let mut result = Vec::new();
+    for v in 0..N {
+        for u in 0..N {
+            if (3..=6).contains(&(u + v)) {
+                result.push(v * N + u);
+            }
+        }
+    }
+    return result;
+}
+
+// BT.601 luma
+fn luminance_of_block(img: &PPMImage, bx: usize, by: usize) -> [f64; N * N] {
+    let mut block = [0.0; N * N];
+    for y in 0..N {
+        for x in 0..N {
+            let color = RGBColor::<f64>::from(img.data[(by * N + y) * img.width + bx * N + x]);
+            block[y * N + x] = 0.299 * color.red + 0.587 * color.green + 0.114 * color.blue;
+        }
+    }
+    return block;
+}
+
+fn cosine_table() -> [[f64; N]; N] {
+    let mut table = [[0.0; N]; N];
+    for (u, row) in table.iter_mut().enumerate() {
+        let scale = if u == 0 {
+            (1.0 / N as f64).sqrt()
+        } else {
+            (2.0 / N as f64).sqrt()
+        };
+        for (x, value) in row.iter_mut().enumerate() {
+            *value =
+                scale * (((2 * x + 1) * u) as f64 * std::f64::consts::PI / (2 * N) as f64).cos();
+        }
+    }
+    return table;
+}
+
+// Orthonormal DCT-II, rows then columns
+fn forward_dct(block: &[f64; N * N]) -> [f64; N * N] {
+    let table = cosine_table();
+    let mut rows = [0.0; N * N];
+    let mut result = [0.0; N * N];
+
+    for y in 0..N {
+        for u in 0..N {
+            rows[y * N + u] = (0..N).map(|x| table[u][x] * block[y * N + x]).sum();
+        }
+    }
+    for v in 0..N {
+        for u in 0..N {
+            result[v * N + u] = (0..N).map(|y| table[v][y] * rows[y * N + u]).sum();
+        }
+    }
+
+    return result;
+}
+
+fn inverse_dct(coefficients: &[f64; N * N]) -> [f64; N * N] {
+    let table = cosine_table();
+    let mut columns = [0.0; N * N];
+    let mut result = [0.0; N * N];
+
+    for y in 0..N {
+        for u in 0..N {
+            columns[y * N + u] = (0..N).map(|v| table[v][y] * coefficients[v * N + u]).sum();
+        }
+    }
+    for y in 0..N {
+        for x in 0..N {
+            result[y * N + x] = (0..N).map(|u| table[u][x] * columns[y * N + u]).sum();
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SplitMix64;
+
+    // Random samples away from black and white, where no projection gets clipped
+    fn image(width: usize, height: usize, seed: u64) -> PPMImage {
+        let mut rng = SplitMix64::new(seed);
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (width, height, 255);
+        img.data = (0..width * height)
+            .map(|_| {
+                let mut sample = || 64 + rng.below(128) as i32;
+                return RGBColor::new(sample(), sample(), sample());
+            })
+            .collect();
+        return img;
+    }
+
+    #[test]
+    fn inverse_dct_undoes_forward_dct() {
+        let mut rng = SplitMix64::new(3);
+        let mut block = [0.0; N * N];
+        for sample in block.iter_mut() {
+            *sample = rng.below(256) as f64;
+        }
+        let back = inverse_dct(&forward_dct(&block));
+        assert!(block.iter().zip(&back).all(|(a, b)| (a - b).abs() < 1e-9));
+    }
+
+    #[test]
+    fn negative_coefficients_carry_the_inverted_parity() {
+        assert_eq!(
+            [-3, -2, -1, 1, 2, 3].map(coefficient_bit),
+            [0, 1, 0, 1, 0, 1]
+        );
+    }
+
+    #[test]
+    fn bad_parameters_are_refused() {
+        for (step, matrix_k) in [(1.5, 2), (f64::NAN, 2), (10.0, 0), (10.0, 8)] {
+            let err = DCTStegano::new(step, matrix_k, None).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn secrets_as_large_as_the_capacity_come_back_whole() {
+        let cover = image(64, 64, 5);
+        for matrix_k in 1..=4 {
+            let algorithm = DCTStegano::new(10.0, matrix_k, Some(String::from("key"))).unwrap();
+            let capacity = algorithm.capacity(&cover);
+            assert!(capacity > 0);
+            let secret = image(capacity, 1, matrix_k as u64);
+            let (stego, key) = algorithm.embed(&cover, &secret).unwrap();
+            assert_eq!(algorithm.extract(&stego, &key).unwrap().data, secret.data);
+        }
+    }
+}
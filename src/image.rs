@@ -1,18 +1,30 @@
-use std::collections::VecDeque;
-use std::{fs, io::Write, str};
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
 
+use crate::codecs;
 use crate::color::RGBColor;
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel, Primitive};
 
-#[derive(Debug)]
-pub struct PPMImage {
+// Image with one `P` per pixel, RGB unless stated otherwise. Samples can be any `Primitive`, e.g.
+// `PPMImage<RGBColor<u8>>` takes a quarter of the memory of the i32 default for 8 bit images.
+// Named after the netpbm formats it started with, see `codecs` for the others.
+#[derive(Debug, Clone)]
+pub struct PPMImage<P = RGBColor<i32>> {
     pub img_type: String,
     pub width: usize,
     pub height: usize,
     pub max_val: usize,
-    pub data: Vec<RGBColor<i32>>,
+    pub data: Vec<P>,
 }
 
-impl PPMImage {
+impl<P> Default for PPMImage<P> {
+    fn default() -> Self {
+        return PPMImage::new();
+    }
+}
+
+impl<P> PPMImage<P> {
     pub fn new() -> Self {
         return PPMImage {
             img_type: String::new(),
@@ -22,97 +34,596 @@ impl PPMImage {
             data: Vec::new(),
         };
     }
+}
 
-    // FIXME: This version currently will not parse comment
-    pub fn from_file(file_path: &str) -> PPMImage {
-        let mut img = PPMImage::new();
-        let mut contents = VecDeque::from(fs::read(file_path).expect("Could not open file"));
-
-        // first 2 bytes will always is in {P1 -> P6}
-        img.img_type = vec![
-            contents.pop_front().unwrap() as char,
-            contents.pop_front().unwrap() as char,
-        ]
-        .iter()
-        .collect();
-
-        img.width = PPMImage::parse_next_normal_number_from_header(&mut contents);
-        img.height = PPMImage::parse_next_normal_number_from_header(&mut contents);
-        img.max_val = PPMImage::parse_next_normal_number_from_header(&mut contents);
-
-        for _ in 0..img.width * img.height {
-            let color = PPMImage::parse_next_color(&mut contents);
-            img.data.push(color);
+impl PPMImage {
+    pub fn from_file(file_path: &str) -> std::io::Result<PPMImage> {
+        return PPMImage::from_netpbm(file_path);
+    }
+}
+
+impl<P: Pixel> PPMImage<P> {
+    // Reads every netpbm type (P1 to P7) of any layout and converts the pixels to `P`, see
+    // `to_rgba` and `from_rgba` for how channels are added or dropped. Bitmaps (P1, P4) come out
+    // as gray with a max_val of 1, white being 1.
+    pub fn from_netpbm(file_path: &str) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_netpbm(BufReader::new(fs::File::open(file_path)?));
+    }
+
+    pub fn from_netpbm_bytes(bytes: &[u8]) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_netpbm(bytes);
+    }
+
+    // Parses the header then reads the raster a row at a time, nothing past the image is
+    // consumed so several images can be read from one stream
+    pub fn read_netpbm<R: BufRead>(reader: R) -> std::io::Result<PPMImage<P>> {
+        return PPMImage::read_netpbm_with_limits(reader, &Limits::default());
+    }
+
+    pub fn read_netpbm_with_limits<R: BufRead>(
+        reader: R,
+        limits: &Limits,
+    ) -> std::io::Result<PPMImage<P>> {
+        let mut reader = HeaderReader::new(reader);
+        let (img_type, header) = reader.netpbm_header()?;
+        limits.check_image::<P>(header.width, header.height)?;
+        let (depth, sample_bytes) = (header.depth(), header.sample_bytes());
+        let plain = matches!(img_type.as_str(), "P1" | "P2" | "P3");
+        if !plain {
+            // a single whitespace character separates the header from the raster
+            reader.read_exact(&mut [0])?;
+        }
+
+        let mut img = PPMImage {
+            img_type,
+            width: header.width,
+            height: header.height,
+            max_val: pixel::stored_max_val::<P>(header.max_val),
+            // grows as rows come in rather than trusting the header with a huge allocation
+            data: Vec::with_capacity((header.width * header.height).min(1 << 20)),
+        };
+        let above_max_val = || Error::new(ErrorKind::InvalidData, "Netpbm sample is above max_val");
+        let (mut row, mut samples) = (Vec::new(), Vec::new());
+        for _ in 0..header.height {
+            // pieces of a row at a time, buffers only grow as far as the input actually goes
+            for start in (0..header.width).step_by(PIXELS_PER_READ) {
+                let pixels = PIXELS_PER_READ.min(header.width - start);
+                samples.clear();
+                match img.img_type.as_str() {
+                    // plain bitmap digits need no whitespace between them, 1 is black
+                    "P1" => {
+                        for _ in 0..pixels {
+                            samples.push(1 - reader.next_bit()?);
+                        }
+                    }
+                    "P2" | "P3" => {
+                        for _ in 0..pixels * depth {
+                            let sample = reader.next_number()?;
+                            if sample > header.max_val {
+                                return Err(above_max_val());
+                            }
+                            samples.push(sample as i32);
+                        }
+                    }
+                    // rows are padded to a whole byte, pieces are a whole number of bytes
+                    "P4" => {
+                        row.resize(pixels.div_ceil(8), 0);
+                        reader.read_exact(&mut row)?;
+                        samples.extend(
+                            (0..pixels).map(|x| 1 - (row[x / 8] >> (7 - x % 8) & 1) as i32),
+                        );
+                    }
+                    _ => {
+                        row.resize(pixels * depth * sample_bytes, 0);
+                        reader.read_exact(&mut row)?;
+                        samples.extend(row.chunks_exact(sample_bytes).map(|bytes| {
+                            if sample_bytes == 1 {
+                                return bytes[0] as i32;
+                            }
+                            return u16::from_be_bytes([bytes[0], bytes[1]]) as i32;
+                        }));
+                        if samples.iter().any(|&sample| sample > header.max_val as i32) {
+                            return Err(above_max_val());
+                        }
+                    }
+                }
+
+                for pixel in samples.chunks_exact(depth) {
+                    img.data
+                        .push(pixel::from_rgba(&header.to_rgba(pixel), header.max_val));
+                }
+            }
         }
 
-        return img;
+        return Ok(img);
     }
 
-    pub fn export_to_file(self, file_path: &str) -> std::io::Result<()> {
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&file_path)?;
-        let mut buffer: Vec<u8> = Vec::new();
+    // Into another layout or sample type, samples are rescaled if max_val does not fit the new
+    // sample type
+    pub fn convert_pixels<Q: Pixel>(&self) -> PPMImage<Q> {
+        let max_val = self.max_val as i32;
+        return PPMImage {
+            img_type: self.img_type.clone(),
+            width: self.width,
+            height: self.height,
+            max_val: pixel::stored_max_val::<Q>(self.max_val),
+            data: self
+                .data
+                .iter()
+                .map(|color| pixel::from_rgba(&pixel::to_rgba(color, max_val), self.max_val))
+                .collect(),
+        };
+    }
 
-        writeln!(&mut file, "{}", self.img_type)?;
-        writeln!(&mut file, "{} {}", self.width, self.height)?;
-        writeln!(&mut file, "{}", self.max_val)?;
+    // P5 for gray, P6 for RGB and P7 for layouts with alpha or images read from a P7 file,
+    // samples take two bytes when max_val is above 255 and are clamped to 0..=max_val
+    pub fn export_to_file(&self, file_path: &str) -> std::io::Result<()> {
+        return codecs::write_atomically(file_path, |writer| self.write_netpbm(writer));
+    }
 
-        for value in self.data {
-            buffer.push(value.red as u8);
-            buffer.push(value.green as u8);
-            buffer.push(value.blue as u8);
+    pub fn write_netpbm<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        // the header would not match clamped samples
+        if !codecs::ImageFormat::Netpbm.stores_max_val(self.max_val) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Netpbm stores samples up to 1 to 65535, got {}",
+                    self.max_val
+                ),
+            ));
         }
-        file.write_all(&buffer)?;
+        if P::has_alpha() || self.img_type == "P7" {
+            writeln!(writer, "P7")?;
+            writeln!(writer, "WIDTH {}", self.width)?;
+            writeln!(writer, "HEIGHT {}", self.height)?;
+            writeln!(writer, "DEPTH {}", P::CHANNELS)?;
+            writeln!(writer, "MAXVAL {}", self.max_val)?;
+            writeln!(writer, "TUPLTYPE {}", PPMImage::<P>::tuple_type())?;
+            writeln!(writer, "ENDHDR")?;
+        } else {
+            writeln!(writer, "{}", PPMImage::<P>::default_img_type())?;
+            writeln!(writer, "{} {}", self.width, self.height)?;
+            writeln!(writer, "{}", self.max_val)?;
+        }
+
+        // a row at a time, the raster is never held twice
+        let max_val = self.max_val as i32;
+        let sample_bytes = if max_val < 256 { 1 } else { 2 };
+        let mut buffer = Vec::with_capacity(self.width * P::CHANNELS * sample_bytes);
+        for row in self.data.chunks(self.width.max(1)) {
+            buffer.clear();
+            for value in row {
+                for c in 0..P::CHANNELS {
+                    let sample = value.channel(c).to_i32().clamp(0, max_val);
+                    if sample_bytes == 1 {
+                        buffer.push(sample as u8);
+                    } else {
+                        buffer.extend_from_slice(&(sample as u16).to_be_bytes());
+                    }
+                }
+            }
+            writer.write_all(&buffer)?;
+        }
+
+        return writer.flush();
+    }
+
+    // Netpbm type of the layout: P5 for gray, P6 for RGB and P7 for layouts with alpha
+    pub(crate) fn default_img_type() -> &'static str {
+        if P::has_alpha() {
+            return "P7";
+        } else if P::CHANNELS == 1 {
+            return "P5";
+        }
+        return "P6";
+    }
 
-        Ok(())
+    fn tuple_type() -> &'static str {
+        return match (P::COLOR_CHANNELS, P::has_alpha()) {
+            (1, false) => "GRAYSCALE",
+            (1, true) => "GRAYSCALE_ALPHA",
+            (_, false) => "RGB",
+            (_, true) => "RGB_ALPHA",
+        };
     }
+}
+
+pub(crate) struct NetpbmHeader {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) max_val: usize,
+    pub(crate) color_channels: usize,
+    pub(crate) alpha: bool,
+}
+
+impl NetpbmHeader {
+    pub(crate) fn depth(&self) -> usize {
+        return self.color_channels + self.alpha as usize;
+    }
+
+    pub(crate) fn sample_bytes(&self) -> usize {
+        return if self.max_val < 256 { 1 } else { 2 };
+    }
+
+    // `depth` samples of one pixel
+    pub(crate) fn to_rgba(&self, samples: &[i32]) -> pixel::RGBAColor<i32> {
+        let max_val = self.max_val as i32;
+        return match (self.color_channels, self.alpha) {
+            (1, false) => pixel::RGBAColor::new(samples[0], samples[0], samples[0], max_val),
+            (1, true) => pixel::RGBAColor::new(samples[0], samples[0], samples[0], samples[1]),
+            (_, false) => pixel::RGBAColor::new(samples[0], samples[1], samples[2], max_val),
+            (_, true) => pixel::RGBAColor::new(samples[0], samples[1], samples[2], samples[3]),
+        };
+    }
+
+    // Samples of `color` in the layout of the header, the first `depth` are used
+    pub(crate) fn samples(&self, color: &pixel::RGBAColor<i32>) -> [i32; 4] {
+        let gray = || pixel::from_rgba::<pixel::GrayColor<i32>>(color, self.max_val).channel(0);
+        return match (self.color_channels, self.alpha) {
+            (1, false) => [gray(), 0, 0, 0],
+            (1, true) => [gray(), color.alpha, 0, 0],
+            _ => [color.red, color.green, color.blue, color.alpha],
+        };
+    }
+}
 
-    fn parse_next_normal_number_from_header(contents: &mut VecDeque<u8>) -> usize {
-        let mut buffer: Vec<char> = Vec::new();
-        let mut character = PPMImage::remove_whitespace(contents);
+// Pixels of a row read at once, a multiple of 8 for bitmaps
+pub(crate) const PIXELS_PER_READ: usize = 1 << 14;
+// Longest header token accepted, numbers and PAM fields are far shorter
+const MAX_TOKEN_LENGTH: usize = 64;
 
-        while !character.is_whitespace() {
-            buffer.push(character);
-            character = contents.pop_front().unwrap() as char;
+// Netpbm style header over any buffered reader, also used by PFM
+pub(crate) struct HeaderReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> HeaderReader<R> {
+    pub(crate) fn new(reader: R) -> HeaderReader<R> {
+        return HeaderReader { reader };
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        return self.reader;
+    }
+
+    // Type and header of any netpbm image, checked for sane dimensions and max_val. Bitmaps get a
+    // max_val of 1.
+    pub(crate) fn netpbm_header(&mut self) -> std::io::Result<(String, NetpbmHeader)> {
+        let img_type = self.next_token()?;
+        let header = match img_type.as_str() {
+            "P1" | "P4" => NetpbmHeader {
+                width: self.next_number()?,
+                height: self.next_number()?,
+                max_val: 1,
+                color_channels: 1,
+                alpha: false,
+            },
+            "P2" | "P3" | "P5" | "P6" => {
+                let (width, height) = (self.next_number()?, self.next_number()?);
+                let max_val = self.next_number()?;
+                let color_channels = if img_type == "P2" || img_type == "P5" {
+                    1
+                } else {
+                    3
+                };
+                NetpbmHeader {
+                    width,
+                    height,
+                    max_val,
+                    color_channels,
+                    alpha: false,
+                }
+            }
+            "P7" => self.pam_header()?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unsupported netpbm type {:?}", img_type),
+                ))
+            }
+        };
+
+        if header.width == 0 || header.height == 0 || !(1..=65535).contains(&header.max_val) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid netpbm header"));
+        }
+        if header.width.checked_mul(header.height).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Netpbm image is too large",
+            ));
         }
+        return Ok((img_type, header));
+    }
+
+    fn peek(&mut self) -> std::io::Result<Option<u8>> {
+        return Ok(self.reader.fill_buf()?.first().copied());
+    }
 
-        return vec_to_u32(&buffer).unwrap() as usize;
+    pub(crate) fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        return self.reader.read_exact(buffer).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                return Error::new(ErrorKind::UnexpectedEof, "Truncated netpbm image");
+            }
+            return err;
+        });
     }
 
-    fn remove_whitespace(contents: &mut VecDeque<u8>) -> char {
-        let mut character = contents.pop_front().unwrap_or_default() as char;
-        while character.is_whitespace() {
-            character = contents.pop_front().unwrap_or_default() as char;
+    // `#` comments run to the end of the line
+    fn skip_whitespace(&mut self) -> std::io::Result<()> {
+        loop {
+            match self.peek()? {
+                Some(byte) if byte.is_ascii_whitespace() => self.reader.consume(1),
+                Some(b'#') => {
+                    while !matches!(self.peek()?, Some(b'\n') | None) {
+                        self.reader.consume(1);
+                    }
+                }
+                _ => return Ok(()),
+            }
         }
-        return character;
     }
 
-    fn remove_newline(contents: &mut VecDeque<u8>) -> char {
-        let mut character = contents.pop_front().unwrap_or_default() as char;
-        while character == '\n' || character == '\r' {
-            character = contents.pop_front().unwrap_or_default() as char;
+    pub(crate) fn next_token(&mut self) -> std::io::Result<String> {
+        self.skip_whitespace()?;
+        let mut token = Vec::new();
+        while let Some(byte) = self.peek()? {
+            if byte.is_ascii_whitespace() {
+                break;
+            }
+            // no header field comes close, don't buffer a binary file looking for the end
+            if token.len() == MAX_TOKEN_LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid netpbm header"));
+            }
+            token.push(byte);
+            self.reader.consume(1);
         }
+        if token.is_empty() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Truncated netpbm header",
+            ));
+        }
+        return String::from_utf8(token)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid netpbm header"));
+    }
 
-        return character;
+    pub(crate) fn next_number(&mut self) -> std::io::Result<usize> {
+        let token = self.next_token()?;
+        return token.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected a number in netpbm header, got {:?}", token),
+            )
+        });
     }
 
-    fn parse_next_color(contents: &mut VecDeque<u8>) -> RGBColor<i32> {
-        let (r, g, b);
+    fn next_bit(&mut self) -> std::io::Result<i32> {
+        self.skip_whitespace()?;
+        let bit = match self.peek()? {
+            Some(b'0') => 0,
+            Some(b'1') => 1,
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Expected 0 or 1 in plain bitmap",
+                ))
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Truncated netpbm image",
+                ))
+            }
+        };
+        self.reader.consume(1);
+        return Ok(bit);
+    }
 
-        r = PPMImage::remove_newline(contents) as i32;
-        g = PPMImage::remove_newline(contents) as i32;
-        b = PPMImage::remove_newline(contents) as i32;
+    // PAM header lines up to ENDHDR, the tuple type defaults to the usual one for the depth
+    fn pam_header(&mut self) -> std::io::Result<NetpbmHeader> {
+        let (mut width, mut height, mut depth, mut max_val) = (0, 0, 0, 0);
+        let mut tuple_type = None;
+        loop {
+            let token = self.next_token()?;
+            match token.as_str() {
+                "WIDTH" => width = self.next_number()?,
+                "HEIGHT" => height = self.next_number()?,
+                "DEPTH" => depth = self.next_number()?,
+                "MAXVAL" => max_val = self.next_number()?,
+                "TUPLTYPE" => tuple_type = Some(self.next_token()?),
+                "ENDHDR" => break,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown PAM header field {:?}", token),
+                    ))
+                }
+            }
+        }
 
-        return RGBColor::new(r, g, b);
+        let (color_channels, alpha) = match (tuple_type.as_deref(), depth) {
+            (Some("GRAYSCALE" | "BLACKANDWHITE"), 1) | (None, 1) => (1, false),
+            (Some("GRAYSCALE_ALPHA" | "BLACKANDWHITE_ALPHA"), 2) | (None, 2) => (1, true),
+            (Some("RGB"), 3) | (None, 3) => (3, false),
+            (Some("RGB_ALPHA"), 4) | (None, 4) => (3, true),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Unsupported PAM tuple type {:?} of depth {}",
+                        tuple_type, depth
+                    ),
+                ))
+            }
+        };
+        return Ok(NetpbmHeader {
+            width,
+            height,
+            max_val,
+            color_channels,
+            alpha,
+        });
     }
 }
 
-pub fn vec_to_u32(digits: &Vec<char>) -> Option<u32> {
-    const RADIX: u32 = 10;
-    return digits
-        .iter()
-        .map(|c| c.to_digit(RADIX))
-        .try_fold(0, |ans, i| i.map(|i| ans * RADIX + i));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::{GrayColor, RGBAColor};
+
+    fn rgb(data: &[[i32; 3]]) -> Vec<RGBColor<i32>> {
+        return data
+            .iter()
+            .map(|&[r, g, b]| RGBColor::new(r, g, b))
+            .collect();
+    }
+
+    #[test]
+    fn plain_ppm_with_comments() {
+        let bytes = b"P3\n# a comment\n2 2 # another\n255\n255 0 0  0 255 0\n0 0 255  10 20 30\n";
+        let img = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(bytes).unwrap();
+        assert_eq!((img.img_type.as_str(), img.width, img.height), ("P3", 2, 2));
+        assert_eq!(img.max_val, 255);
+        assert_eq!(
+            img.data,
+            rgb(&[[255, 0, 0], [0, 255, 0], [0, 0, 255], [10, 20, 30]])
+        );
+    }
+
+    #[test]
+    fn raw_samples_equal_to_whitespace() {
+        // 10 and 13 are newline and carriage return, the raster is read as bytes all the same
+        let mut bytes = b"P6\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[10, 13, 32, 9, 0, 255]);
+        let img = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(img.data, rgb(&[[10, 13, 32], [9, 0, 255]]));
+    }
+
+    #[test]
+    fn sixteen_bit_gray() {
+        let mut bytes = b"P5 2 1 65535\n".to_vec();
+        bytes.extend_from_slice(&[0x12, 0x34, 0xff, 0xff]);
+        let img = PPMImage::<GrayColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(img.max_val, 65535);
+        assert_eq!(
+            img.data,
+            vec![GrayColor::new(0x1234), GrayColor::new(65535)]
+        );
+    }
+
+    #[test]
+    fn plain_and_raw_bitmaps_agree() {
+        let plain = PPMImage::<GrayColor<i32>>::from_netpbm_bytes(b"P1\n10 1\n1010000001\n");
+        // rows are padded to a whole byte
+        let raw = PPMImage::<GrayColor<i32>>::from_netpbm_bytes(b"P4\n10 1\n\xa0\x40");
+        let (plain, raw) = (plain.unwrap(), raw.unwrap());
+        assert_eq!(plain.max_val, 1);
+        assert_eq!(plain.data, raw.data);
+        // 1 is black
+        assert_eq!(plain.data[0], GrayColor::new(0));
+        assert_eq!(plain.data[1], GrayColor::new(1));
+    }
+
+    #[test]
+    fn pam_with_alpha() {
+        let mut bytes =
+            b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n".to_vec();
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        let img = PPMImage::<RGBAColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(img.data, vec![RGBAColor::new(1, 2, 3, 4)]);
+        // dropped when read without one
+        let img = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(img.data, rgb(&[[1, 2, 3]]));
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (3, 2, 255);
+        img.data = rgb(&[
+            [0, 1, 2],
+            [10, 13, 255],
+            [4, 5, 6],
+            [7, 8, 9],
+            [100, 200, 50],
+            [255, 255, 255],
+        ]);
+        let mut bytes = Vec::new();
+        img.write_netpbm(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"P6\n3 2\n255\n"));
+        let read = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!((read.width, read.height, read.max_val), (3, 2, 255));
+        assert_eq!(read.data, img.data);
+
+        img.max_val = 1000;
+        *img.data[0].channel_mut(0) = 1000;
+        // out of range samples are clamped
+        *img.data[1].channel_mut(1) = -5;
+        let mut bytes = Vec::new();
+        img.write_netpbm(&mut bytes).unwrap();
+        let read = PPMImage::<RGBColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(read.data[0], RGBColor::new(1000, 1, 2));
+        assert_eq!(read.data[1], RGBColor::new(10, 0, 255));
+    }
+
+    #[test]
+    fn write_then_read_with_alpha() {
+        let mut img = PPMImage::<RGBAColor<i32>>::new();
+        (img.width, img.height, img.max_val) = (1, 2, 255);
+        img.data = vec![RGBAColor::new(1, 2, 3, 4), RGBAColor::new(5, 6, 7, 8)];
+        let mut bytes = Vec::new();
+        img.write_netpbm(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"P7\n"));
+        let read = PPMImage::<RGBAColor<i32>>::from_netpbm_bytes(&bytes).unwrap();
+        assert_eq!(read.data, img.data);
+    }
+
+    #[test]
+    fn several_images_in_one_stream() {
+        let bytes = b"P2 1 1 255 7\nP2 1 1 255 9\n";
+        let mut reader = &bytes[..];
+        let first = PPMImage::<GrayColor<i32>>::read_netpbm(&mut reader).unwrap();
+        let second = PPMImage::<GrayColor<i32>>::read_netpbm(&mut reader).unwrap();
+        assert_eq!(first.data, vec![GrayColor::new(7)]);
+        assert_eq!(second.data, vec![GrayColor::new(9)]);
+    }
+
+    #[test]
+    fn max_vals_netpbm_cannot_store_are_refused() {
+        for max_val in [0, 65536] {
+            let mut img = PPMImage::<GrayColor<i32>>::new();
+            (img.width, img.height, img.max_val) = (1, 1, max_val);
+            img.data = vec![GrayColor::new(0)];
+            let err = img.write_netpbm(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let err = PPMImage::from_file("./images/no_such_image.ppm").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn invalid_images_are_errors() {
+        let invalid: [&[u8]; 8] = [
+            b"",
+            b"P9 1 1 255\n",
+            b"P6 2 2 255\n\x00\x00\x00",
+            b"P3 1 1 255\n1 2 256\n",
+            b"P5 1 1 3\n\x07",
+            b"P6 100000 100000 255\n\x01\x02\x03",
+            b"P2 1 1 0\n0\n",
+            b"P6 99999999999999999999 1 255\n",
+        ];
+        for bytes in invalid {
+            assert!(
+                PPMImage::<RGBColor<i32>>::from_netpbm_bytes(bytes).is_err(),
+                "{:?}",
+                String::from_utf8_lossy(bytes)
+            );
+        }
+    }
 }
@@ -0,0 +1,21 @@
+// explicit returns, and offsets like `(y + 0)` lined up with `(y + 1)`, are the style of this crate
+#![allow(clippy::needless_return, clippy::identity_op)]
+
+extern crate quicksort;
+
+pub mod algorithm;
+pub mod codecs;
+pub mod color;
+pub mod dct;
+pub mod image;
+pub mod limits;
+pub mod lsb;
+pub mod pixel;
+pub mod planar;
+pub mod progress;
+pub mod pvd;
+mod random;
+pub mod steganalysis;
+pub mod stegano;
+pub mod tiled;
+pub mod watermark;
@@ -0,0 +1,179 @@
+use std::io::{Error, ErrorKind};
+
+use crate::pixel::Pixel;
+
+// Ceilings on what decoding an untrusted image or stego key may allocate, checked against the
+// header before anything is allocated. The defaults fit any image up to 16384x16384 pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_pixels: usize, // of one image or page
+    pub max_bytes: usize,  // of its pixels once decoded, and of an encoded image read whole
+    pub max_key_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        return Limits {
+            max_pixels: 1 << 28,
+            max_bytes: 1 << 32,
+            max_key_bytes: 1 << 26,
+        };
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Limits {
+        return Limits {
+            max_pixels: usize::MAX,
+            max_bytes: usize::MAX,
+            max_key_bytes: usize::MAX,
+        };
+    }
+
+    // Pixel count of a `width` x `height` image decoded as `P`
+    pub fn check_image<P: Pixel>(&self, width: usize, height: usize) -> std::io::Result<usize> {
+        let pixels = width
+            .checked_mul(height)
+            .filter(|&pixels| pixels <= self.max_pixels)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Image of {}x{} pixels is over the limit of {} pixels",
+                        width, height, self.max_pixels
+                    ),
+                )
+            })?;
+        if pixels
+            .checked_mul(std::mem::size_of::<P>())
+            .is_none_or(|bytes| bytes > self.max_bytes)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Image of {}x{} pixels is over the limit of {} bytes",
+                    width, height, self.max_bytes
+                ),
+            ));
+        }
+        return Ok(pixels);
+    }
+
+    // Length of an encoded image that has to be read whole before decoding
+    pub fn check_input(&self, bytes: usize) -> std::io::Result<()> {
+        if bytes > self.max_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Image file is over the limit of {} bytes", self.max_bytes),
+            ));
+        }
+        return Ok(());
+    }
+
+    pub fn check_key(&self, bytes: usize) -> std::io::Result<()> {
+        if bytes > self.max_key_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Stego key is over the limit of {} bytes",
+                    self.max_key_bytes
+                ),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::{StegoAlgorithm, StegoKey};
+    use crate::codecs;
+    use crate::color::RGBColor;
+    use crate::dct::DCTStegano;
+    use crate::image::PPMImage;
+    use crate::lsb::LSBStegano;
+    use crate::pvd::PVDStegano;
+    use crate::stegano::DWTStegano;
+
+    fn limits(max_pixels: usize, max_bytes: usize) -> Limits {
+        return Limits {
+            max_pixels,
+            max_bytes,
+            ..Limits::default()
+        };
+    }
+
+    #[test]
+    fn images_over_the_limits_are_refused() {
+        let limits = limits(100, 1200);
+        assert_eq!(limits.check_image::<RGBColor<i32>>(10, 10).unwrap(), 100);
+        assert!(limits.check_image::<RGBColor<i32>>(11, 10).is_err());
+        assert!(limits.check_image::<RGBColor<i32>>(usize::MAX, 2).is_err());
+        // 100 pixels of 24 bytes
+        assert!(limits.check_image::<RGBColor<f64>>(10, 10).is_err());
+        assert!(Limits::unlimited()
+            .check_image::<RGBColor<i32>>(1 << 20, 1 << 20)
+            .is_ok());
+    }
+
+    #[test]
+    fn huge_headers_are_refused_before_allocating() {
+        let header = b"P6\n100000 100000\n255\n\x01\x02\x03";
+        let result = codecs::decode_image_with_limits::<RGBColor<i32>>(header, &Limits::default());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (20, 10, 255);
+        img.data = vec![RGBColor::new(1, 2, 3); 200];
+        let mut bytes = Vec::new();
+        for format in [
+            codecs::ImageFormat::Netpbm,
+            codecs::ImageFormat::Png,
+            codecs::ImageFormat::Bmp,
+            codecs::ImageFormat::Tga,
+            codecs::ImageFormat::Tiff,
+            codecs::ImageFormat::Qoi,
+        ] {
+            bytes.clear();
+            codecs::write_image(&img, &mut bytes, format, false).unwrap();
+            let small = limits(199, usize::MAX);
+            assert!(codecs::decode_image_with_limits::<RGBColor<i32>>(&bytes, &small).is_err());
+            let fitting = limits(200, usize::MAX);
+            let decoded =
+                codecs::decode_image_with_limits::<RGBColor<i32>>(&bytes, &fitting).unwrap();
+            assert_eq!(decoded.data, img.data);
+        }
+        // only the start of a whole input over the limit is read
+        let small = limits(usize::MAX, bytes.len() - 1);
+        assert!(codecs::read_image_with_limits::<RGBColor<i32>, _>(&bytes[..], &small).is_err());
+    }
+
+    #[test]
+    fn forged_keys_are_refused_before_allocating() {
+        let key = StegoKey::new(1 << 20, 1 << 20);
+        let limits = limits(1 << 30, usize::MAX);
+        assert!(StegoKey::from_bytes_with_limits(&key.to_bytes(), &limits).is_err());
+        let bytes = StegoKey::new(8, 8).to_bytes();
+        let limits = Limits {
+            max_key_bytes: bytes.len() - 1,
+            ..Limits::default()
+        };
+        assert!(StegoKey::from_bytes_with_limits(&bytes, &limits).is_err());
+
+        let mut stego = PPMImage::new();
+        stego.img_type = String::from("P6");
+        (stego.width, stego.height, stego.max_val) = (16, 16, 255);
+        stego.data = vec![RGBColor::new(100, 120, 140); 256];
+        let algorithms: [&dyn StegoAlgorithm<RGBColor<i32>>; 4] = [
+            &LSBStegano::new(4, None).unwrap(),
+            &PVDStegano::new(None),
+            &DCTStegano::default(),
+            &DWTStegano::default(),
+        ];
+        for algorithm in algorithms {
+            assert!(algorithm.extract(&stego, &key).is_err());
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::io::{Error, ErrorKind};
+
+use crate::algorithm::{self, StegoAlgorithm, StegoKey, BITS_PER_SECRET_PIXEL};
+use crate::image::PPMImage;
+use crate::pixel::Pixel;
+use crate::random::SplitMix64;
+
+// Replaces the `bits` lowest bits of every channel sample of the cover, either in raster order
+// or in an order shuffled by a password
+#[derive(Debug, Clone)]
+pub struct LSBStegano {
+    bits: u8,
+    password: Option<String>,
+}
+
+impl LSBStegano {
+    pub fn new(bits: u8, password: Option<String>) -> std::io::Result<LSBStegano> {
+        if !(1..=4).contains(&bits) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("LSB embedding uses 1 to 4 bits per channel, got {}", bits),
+            ));
+        }
+        return Ok(LSBStegano { bits, password });
+    }
+
+    pub fn sequential(bits: u8) -> std::io::Result<LSBStegano> {
+        return LSBStegano::new(bits, None);
+    }
+
+    pub fn shuffled(bits: u8, password: &str) -> std::io::Result<LSBStegano> {
+        return LSBStegano::new(bits, Some(String::from(password)));
+    }
+
+    // Channel samples (pixel * 3 + channel) in the order they are written
+    fn slot_order(&self, cover: &PPMImage, needed: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..cover.data.len() * 3).collect();
+        if let Some(password) = &self.password {
+            SplitMix64::from_password(password).shuffle(&mut order);
+        }
+        order.truncate(needed);
+        return order;
+    }
+
+    // The lowest bits can only take every value below max_val when max_val ends with them all set,
+    // as 255 or 65535 do
+    fn fits_samples_of(&self, img: &PPMImage) -> bool {
+        return (img.max_val + 1) % (1 << self.bits) == 0;
+    }
+
+    fn slots_needed(&self, width: usize, height: usize) -> usize {
+        return (width * height * BITS_PER_SECRET_PIXEL).div_ceil(self.bits as usize);
+    }
+}
+
+impl StegoAlgorithm for LSBStegano {
+    fn embed(&self, cover: &PPMImage, secret: &PPMImage) -> std::io::Result<(PPMImage, StegoKey)> {
+        if !self.fits_samples_of(cover) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Writing {} bits per sample would go above the maximum value {} of the cover",
+                    self.bits, cover.max_val
+                ),
+            ));
+        }
+        if secret.width * secret.height > self.capacity(cover) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Secret image is too large for this cover",
+            ));
+        }
+
+        let payload = algorithm::secret_to_bytes(secret);
+
+        let mut stego = PPMImage {
+            img_type: String::from("P6"),
+            width: cover.width,
+            height: cover.height,
+            max_val: cover.max_val,
+            data: cover.data.clone(),
+        };
+        let mask = (1 << self.bits) - 1;
+        let total_bits = payload.len() * 8;
+        let mut bit_index = 0;
+
+        for slot in self.slot_order(cover, self.slots_needed(secret.width, secret.height)) {
+            let mut chunk = 0;
+            for _ in 0..self.bits {
+                chunk <<= 1;
+                if bit_index < total_bits {
+                    chunk |= algorithm::bit_of(&payload, bit_index) as i32;
+                }
+                bit_index += 1;
+            }
+            let sample = stego.data[slot / 3].channel_mut(slot % 3);
+            *sample = (*sample & !mask) | chunk;
+        }
+
+        return Ok((stego, StegoKey::new(secret.width, secret.height)));
+    }
+
+    fn extract(&self, stego: &PPMImage, key: &StegoKey) -> std::io::Result<PPMImage> {
+        let pixels = algorithm::secret_pixels(key, self.capacity(stego))?;
+        let mut payload = vec![0_u8; pixels * 3];
+        let total_bits = payload.len() * 8;
+        let mask = (1 << self.bits) - 1;
+        let mut bit_index = 0;
+
+        for slot in self.slot_order(stego, self.slots_needed(key.width, key.height)) {
+            let chunk = stego.data[slot / 3].channel(slot % 3) & mask;
+            for shift in (0..self.bits).rev() {
+                if bit_index < total_bits {
+                    algorithm::set_bit(&mut payload, bit_index, (chunk >> shift) as u8);
+                }
+                bit_index += 1;
+            }
+        }
+
+        return Ok(algorithm::secret_from_bytes(
+            key.width, key.height, &payload,
+        ));
+    }
+
+    fn capacity(&self, cover: &PPMImage) -> usize {
+        if !self.fits_samples_of(cover) {
+            return 0;
+        }
+        return cover.data.len() * 3 * self.bits as usize / BITS_PER_SECRET_PIXEL;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::RGBColor;
+
+    fn image(width: usize, height: usize, max_val: usize) -> PPMImage {
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (width, height, max_val);
+        img.data = (0..width * height)
+            .map(|i| {
+                let sample = (i * 7 % (max_val + 1)) as i32;
+                return RGBColor::new(sample, max_val as i32 - sample, sample / 2);
+            })
+            .collect();
+        return img;
+    }
+
+    #[test]
+    fn bad_bit_counts_are_refused() {
+        for bits in [0, 5] {
+            let err = LSBStegano::sequential(bits).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn samples_stay_within_max_val() {
+        let secret = image(2, 2, 255);
+        for (max_val, bits) in [(255, 4), (1, 1), (15, 2), (1023, 3)] {
+            let cover = image(16, 16, max_val);
+            let algorithm = LSBStegano::shuffled(bits, "key").unwrap();
+            let (stego, key) = algorithm.embed(&cover, &secret).unwrap();
+            let in_range = |color: &RGBColor<i32>| {
+                return (0..3).all(|c| (0..=max_val as i32).contains(&color.channel(c)));
+            };
+            assert!(stego.data.iter().all(in_range));
+            assert_eq!(algorithm.extract(&stego, &key).unwrap().data, secret.data);
+        }
+    }
+
+    #[test]
+    fn covers_whose_max_val_the_bits_would_exceed_are_refused() {
+        let secret = image(1, 1, 255);
+        for (max_val, bits) in [(1, 2), (100, 1), (254, 2)] {
+            let cover = image(16, 16, max_val);
+            let algorithm = LSBStegano::sequential(bits).unwrap();
+            assert_eq!(algorithm.capacity(&cover), 0);
+            let err = algorithm.embed(&cover, &secret).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
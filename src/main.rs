@@ -1,20 +1,121 @@
-mod color;
-mod image;
-mod stegano;
-
-extern crate quicksort;
-use crate::stegano::DWTImage;
-
-fn main() {
-    let (key1, index_arr, width, height) =
-        DWTImage::hide_image("./images/dog.ppm", "./images/banana.ppm").unwrap();
-    DWTImage::extract_message_from_image(
-        "./images/watermarked.ppm",
-        "./images/extracted_img.ppm",
-        key1,
-        index_arr,
-        width,
-        height,
-    )
-    .unwrap();
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::io::{Error, ErrorKind};
+
+use idwteg::algorithm::{self, StegoAlgorithm};
+use idwteg::codecs;
+use idwteg::dct::DCTStegano;
+use idwteg::image::PPMImage;
+use idwteg::lsb::LSBStegano;
+use idwteg::progress::{Monitor, Stage};
+use idwteg::pvd::PVDStegano;
+use idwteg::stegano::DWTStegano;
+use idwteg::tiled;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("capacity") {
+        let cover_path = args.get(2).map_or("./images/dog.ppm", String::as_str);
+        let cover: PPMImage = codecs::load_image(cover_path)?;
+        for name in ["dwt", "lsb", "dct", "pvd"] {
+            println!(
+                "{}: {} secret pixels",
+                name,
+                algorithm_by_name(name)?.capacity(&cover)
+            );
+        }
+        return Ok(());
+    }
+
+    // idwteg tiled [budget in MiB] [algorithm] [cover] [secret] [stego] [extracted], the cover and
+    // stego image are raw netpbm files processed tile by tile
+    if args.get(1).map(String::as_str) == Some("tiled") {
+        let path = |index: usize, default| args.get(index + 2).map_or(default, String::as_str);
+        let budget: usize = match args.get(2) {
+            Some(budget) => budget.parse().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Expected a budget in MiB, got {:?}", budget),
+                )
+            })?,
+            None => 256,
+        };
+        let algorithm = algorithm_by_name(path(1, "dwt"))?;
+        let stego_path = path(4, "./images/watermarked.ppm");
+        let key = tiled::hide_image_tiled(
+            algorithm.as_ref(),
+            path(2, "./images/dog.ppm"),
+            path(3, "./images/banana.ppm"),
+            stego_path,
+            budget
+                .checked_mul(1 << 20)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Budget is too large"))?,
+        )?;
+        tiled::extract_image_tiled(
+            algorithm.as_ref(),
+            stego_path,
+            path(5, "./images/extracted_img.ppm"),
+            &key,
+        )?;
+        return Ok(());
+    }
+
+    // idwteg [algorithm] [cover] [secret] [stego] [extracted], any supported format
+    let path = |index: usize, default| args.get(index).map_or(default, String::as_str);
+    let algorithm = algorithm_by_name(path(1, "dwt"))?;
+    let stego_path = path(4, "./images/watermarked.ppm");
+
+    // a TIFF stego image gets the secret spread across the pages of a TIFF cover
+    if codecs::ImageFormat::from_extension(stego_path) == Some(codecs::ImageFormat::Tiff) {
+        let keys = algorithm::hide_image_in_pages(
+            algorithm.as_ref(),
+            path(2, "./images/dog.tif"),
+            path(3, "./images/banana.ppm"),
+            stego_path,
+        )?;
+        algorithm::extract_image_from_pages(
+            algorithm.as_ref(),
+            stego_path,
+            path(5, "./images/extracted_img.ppm"),
+            &keys,
+        )?;
+        return Ok(());
+    }
+
+    let key = algorithm::hide_image(
+        algorithm.as_ref(),
+        path(2, "./images/dog.ppm"),
+        path(3, "./images/banana.ppm"),
+        stego_path,
+    )?;
+    algorithm::extract_image(
+        algorithm.as_ref(),
+        stego_path,
+        path(5, "./images/extracted_img.ppm"),
+        &key,
+    )?;
+
+    return Ok(());
+}
+
+fn algorithm_by_name(name: &str) -> std::io::Result<Box<dyn StegoAlgorithm>> {
+    return Ok(match name {
+        "lsb" => Box::new(LSBStegano::sequential(2)?),
+        "dct" => Box::new(DCTStegano::default()),
+        "pvd" => Box::new(PVDStegano::default()),
+        _ => Box::new(
+            DWTStegano::default().with_monitor(Monitor::default().with_progress(print_progress)),
+        ),
+    });
+}
+
+// One line per stage on stderr, rewritten as the stage goes
+fn print_progress(stage: Stage, done: usize, total: usize) {
+    if done == total {
+        eprintln!("\r{}: 100%", stage.name());
+    } else {
+        eprint!("\r{}: {}%", stage.name(), done * 100 / total);
+    }
 }
@@ -0,0 +1,368 @@
+use std::fmt::Debug;
+
+use crate::color::RGBColor;
+
+// Most channels a pixel layout has (RGBA)
+pub const MAX_CHANNELS: usize = 4;
+
+// A pixel is a fixed number of channel samples: the color channels (1 for gray, 3 for RGB)
+// followed by an alpha channel if the layout has one
+pub trait Pixel: Copy + Default + Debug + PartialEq {
+    type Sample: Primitive;
+    // The same layout with another sample type, e.g. f64 while inverting the DWT
+    type Of<U: Primitive>: Pixel<Sample = U>;
+
+    const CHANNELS: usize;
+    const COLOR_CHANNELS: usize;
+
+    fn channel(&self, index: usize) -> Self::Sample;
+
+    fn channel_mut(&mut self, index: usize) -> &mut Self::Sample;
+
+    fn has_alpha() -> bool {
+        return Self::CHANNELS > Self::COLOR_CHANNELS;
+    }
+
+    fn map_channels<F: Fn(Self::Sample) -> Self::Sample>(self, f: F) -> Self {
+        let mut result = self;
+        for c in 0..Self::CHANNELS {
+            *result.channel_mut(c) = f(self.channel(c));
+        }
+        return result;
+    }
+
+    fn zip_channels<F>(self, other: Self, f: F) -> Self
+    where
+        F: Fn(Self::Sample, Self::Sample) -> Self::Sample,
+    {
+        let mut result = self;
+        for c in 0..Self::CHANNELS {
+            *result.channel_mut(c) = f(self.channel(c), other.channel(c));
+        }
+        return result;
+    }
+
+    // Channel by channel into a pixel of the same layout with another sample type
+    fn convert<Q: Pixel, F: Fn(Self::Sample) -> Q::Sample>(self, f: F) -> Q {
+        assert_eq!(Self::CHANNELS, Q::CHANNELS, "Pixel layouts do not match");
+        let mut result = Q::default();
+        for c in 0..Self::CHANNELS {
+            *result.channel_mut(c) = f(self.channel(c));
+        }
+        return result;
+    }
+}
+
+// Types samples can be stored as. Pixel values are worked on as i32 (and f64 when inverting the
+// DWT), going back to a narrower type rounds and saturates.
+pub trait Primitive: Copy + Default + Debug + PartialEq {
+    // Largest max_val the type holds exactly
+    const MAX_VAL: usize;
+
+    fn to_i32(self) -> i32;
+
+    fn from_i32(value: i32) -> Self;
+
+    fn to_f64(self) -> f64;
+
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Primitive for u8 {
+    const MAX_VAL: usize = u8::MAX as usize;
+
+    fn to_i32(self) -> i32 {
+        return self as i32;
+    }
+
+    fn from_i32(value: i32) -> u8 {
+        return value.clamp(0, u8::MAX as i32) as u8;
+    }
+
+    fn to_f64(self) -> f64 {
+        return self as f64;
+    }
+
+    fn from_f64(value: f64) -> u8 {
+        return value.round().clamp(0.0, u8::MAX as f64) as u8;
+    }
+}
+
+impl Primitive for u16 {
+    const MAX_VAL: usize = u16::MAX as usize;
+
+    fn to_i32(self) -> i32 {
+        return self as i32;
+    }
+
+    fn from_i32(value: i32) -> u16 {
+        return value.clamp(0, u16::MAX as i32) as u16;
+    }
+
+    fn to_f64(self) -> f64 {
+        return self as f64;
+    }
+
+    fn from_f64(value: f64) -> u16 {
+        return value.round().clamp(0.0, u16::MAX as f64) as u16;
+    }
+}
+
+impl Primitive for i32 {
+    const MAX_VAL: usize = i32::MAX as usize;
+
+    fn to_i32(self) -> i32 {
+        return self;
+    }
+
+    fn from_i32(value: i32) -> i32 {
+        return value;
+    }
+
+    fn to_f64(self) -> f64 {
+        return self as f64;
+    }
+
+    fn from_f64(value: f64) -> i32 {
+        return value.round() as i32;
+    }
+}
+
+impl Primitive for f32 {
+    // integers past 2^24 are no longer exact
+    const MAX_VAL: usize = 1 << 24;
+
+    fn to_i32(self) -> i32 {
+        return self.round() as i32;
+    }
+
+    fn from_i32(value: i32) -> f32 {
+        return value as f32;
+    }
+
+    fn to_f64(self) -> f64 {
+        return self as f64;
+    }
+
+    fn from_f64(value: f64) -> f32 {
+        return value as f32;
+    }
+}
+
+impl Primitive for f64 {
+    const MAX_VAL: usize = i32::MAX as usize;
+
+    fn to_i32(self) -> i32 {
+        return self.round() as i32;
+    }
+
+    fn from_i32(value: i32) -> f64 {
+        return value as f64;
+    }
+
+    fn to_f64(self) -> f64 {
+        return self;
+    }
+
+    fn from_f64(value: f64) -> f64 {
+        return value;
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GrayColor<T> {
+    pub gray: T,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GrayAlphaColor<T> {
+    pub gray: T,
+    pub alpha: T,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RGBAColor<T> {
+    pub red: T,
+    pub green: T,
+    pub blue: T,
+    pub alpha: T,
+}
+
+impl<T> GrayColor<T> {
+    pub fn new(gray: T) -> GrayColor<T> {
+        return GrayColor { gray };
+    }
+}
+
+impl<T> GrayAlphaColor<T> {
+    pub fn new(gray: T, alpha: T) -> GrayAlphaColor<T> {
+        return GrayAlphaColor { gray, alpha };
+    }
+}
+
+impl<T> RGBAColor<T> {
+    pub fn new(red: T, green: T, blue: T, alpha: T) -> RGBAColor<T> {
+        return RGBAColor {
+            red,
+            green,
+            blue,
+            alpha,
+        };
+    }
+}
+
+impl<T: Primitive> Pixel for GrayColor<T> {
+    type Sample = T;
+    type Of<U: Primitive> = GrayColor<U>;
+
+    const CHANNELS: usize = 1;
+    const COLOR_CHANNELS: usize = 1;
+
+    fn channel(&self, index: usize) -> T {
+        return match index {
+            0 => self.gray,
+            _ => panic!("GrayColor has no channel {}", index),
+        };
+    }
+
+    fn channel_mut(&mut self, index: usize) -> &mut T {
+        return match index {
+            0 => &mut self.gray,
+            _ => panic!("GrayColor has no channel {}", index),
+        };
+    }
+}
+
+impl<T: Primitive> Pixel for GrayAlphaColor<T> {
+    type Sample = T;
+    type Of<U: Primitive> = GrayAlphaColor<U>;
+
+    const CHANNELS: usize = 2;
+    const COLOR_CHANNELS: usize = 1;
+
+    // 0 => gray, 1 => alpha
+    fn channel(&self, index: usize) -> T {
+        return match index {
+            0 => self.gray,
+            1 => self.alpha,
+            _ => panic!("GrayAlphaColor has no channel {}", index),
+        };
+    }
+
+    fn channel_mut(&mut self, index: usize) -> &mut T {
+        return match index {
+            0 => &mut self.gray,
+            1 => &mut self.alpha,
+            _ => panic!("GrayAlphaColor has no channel {}", index),
+        };
+    }
+}
+
+impl<T: Primitive> Pixel for RGBColor<T> {
+    type Sample = T;
+    type Of<U: Primitive> = RGBColor<U>;
+
+    const CHANNELS: usize = 3;
+    const COLOR_CHANNELS: usize = 3;
+
+    // 0 => red, 1 => green, 2 => blue
+    fn channel(&self, index: usize) -> T {
+        return match index {
+            0 => self.red,
+            1 => self.green,
+            2 => self.blue,
+            _ => panic!("RGBColor has no channel {}", index),
+        };
+    }
+
+    fn channel_mut(&mut self, index: usize) -> &mut T {
+        return match index {
+            0 => &mut self.red,
+            1 => &mut self.green,
+            2 => &mut self.blue,
+            _ => panic!("RGBColor has no channel {}", index),
+        };
+    }
+}
+
+impl<T: Primitive> Pixel for RGBAColor<T> {
+    type Sample = T;
+    type Of<U: Primitive> = RGBAColor<U>;
+
+    const CHANNELS: usize = 4;
+    const COLOR_CHANNELS: usize = 3;
+
+    // 0 => red, 1 => green, 2 => blue, 3 => alpha
+    fn channel(&self, index: usize) -> T {
+        return match index {
+            0 => self.red,
+            1 => self.green,
+            2 => self.blue,
+            3 => self.alpha,
+            _ => panic!("RGBAColor has no channel {}", index),
+        };
+    }
+
+    fn channel_mut(&mut self, index: usize) -> &mut T {
+        return match index {
+            0 => &mut self.red,
+            1 => &mut self.green,
+            2 => &mut self.blue,
+            3 => &mut self.alpha,
+            _ => panic!("RGBAColor has no channel {}", index),
+        };
+    }
+}
+
+// Any layout as RGBA: gray is copied to red, green and blue, a missing alpha is opaque
+pub fn to_rgba<P: Pixel>(pixel: &P, max_val: i32) -> RGBAColor<i32> {
+    let alpha = if P::has_alpha() {
+        pixel.channel(P::COLOR_CHANNELS).to_i32()
+    } else {
+        max_val
+    };
+    if P::COLOR_CHANNELS == 1 {
+        let gray = pixel.channel(0).to_i32();
+        return RGBAColor::new(gray, gray, gray, alpha);
+    }
+    return RGBAColor::new(
+        pixel.channel(0).to_i32(),
+        pixel.channel(1).to_i32(),
+        pixel.channel(2).to_i32(),
+        alpha,
+    );
+}
+
+// RGBA going up to `max_val` into any layout, gray layouts take the BT.601 luma and the alpha is
+// dropped if the layout has none. Samples are rescaled to `stored_max_val` when `max_val` does
+// not fit the sample type of the layout.
+pub fn from_rgba<P: Pixel>(color: &RGBAColor<i32>, max_val: usize) -> P {
+    let target = stored_max_val::<P>(max_val);
+    let fit = |sample: i32| {
+        if target == max_val {
+            return P::Sample::from_i32(sample);
+        }
+        let sample = sample.clamp(0, max_val as i32) as u64;
+        let max_val = max_val as u64;
+        return P::Sample::from_i32(((sample * target as u64 + max_val / 2) / max_val) as i32);
+    };
+
+    let mut pixel = P::default();
+    if P::COLOR_CHANNELS == 1 {
+        *pixel.channel_mut(0) =
+            fit((299 * color.red + 587 * color.green + 114 * color.blue + 500).div_euclid(1000));
+    } else {
+        *pixel.channel_mut(0) = fit(color.red);
+        *pixel.channel_mut(1) = fit(color.green);
+        *pixel.channel_mut(2) = fit(color.blue);
+    }
+    if P::has_alpha() {
+        *pixel.channel_mut(P::COLOR_CHANNELS) = fit(color.alpha);
+    }
+    return pixel;
+}
+
+// max_val of an image going up to `max_val` once stored as `P`
+pub fn stored_max_val<P: Pixel>(max_val: usize) -> usize {
+    return max_val.min(P::Sample::MAX_VAL);
+}
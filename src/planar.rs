@@ -0,0 +1,406 @@
+use crate::image::PPMImage;
+use crate::pixel::{Pixel, Primitive};
+use crate::stegano::DWTImage;
+
+// Struct of arrays: one plane per channel, each a `width` x `height` raster. The transforms work
+// a row at a time on contiguous samples, which the array of pixels layout of `PPMImage` and
+// `DWTImage` does not allow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Planes<T> {
+    pub width: usize,
+    pub height: usize,
+    pub planes: Vec<Vec<T>>,
+}
+
+impl<T: Primitive> Planes<T> {
+    pub fn new(width: usize, height: usize, channels: usize) -> Planes<T> {
+        return Planes {
+            width,
+            height,
+            planes: vec![vec![T::default(); width * height]; channels],
+        };
+    }
+
+    // Samples are converted like `Primitive::from_f64` does
+    pub fn from_pixels<P: Pixel>(data: &[P], width: usize, height: usize) -> Planes<T> {
+        let mut planes = vec![Vec::with_capacity(data.len()); P::CHANNELS];
+        // a single pass over the pixels, one per plane costs more than the transform
+        for color in data {
+            for (c, plane) in planes.iter_mut().enumerate() {
+                plane.push(T::from_f64(color.channel(c).to_f64()));
+            }
+        }
+        return Planes {
+            width,
+            height,
+            planes,
+        };
+    }
+
+    pub fn to_pixels<P: Pixel<Sample = T>>(&self) -> Vec<P> {
+        assert_eq!(self.planes.len(), P::CHANNELS, "Pixel layouts do not match");
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for i in 0..self.width * self.height {
+            let mut color = P::default();
+            for (c, plane) in self.planes.iter().enumerate() {
+                *color.channel_mut(c) = plane[i];
+            }
+            data.push(color);
+        }
+        return data;
+    }
+}
+
+impl Planes<i32> {
+    pub fn from_image<P: Pixel>(img: &PPMImage<P>) -> Planes<i32> {
+        let mut planes = vec![Vec::with_capacity(img.data.len()); P::CHANNELS];
+        for color in &img.data {
+            for (c, plane) in planes.iter_mut().enumerate() {
+                plane.push(color.channel(c).to_i32());
+            }
+        }
+        return Planes {
+            width: img.width,
+            height: img.height,
+            planes,
+        };
+    }
+}
+
+// Wavelets `PlanarDWT` can transform with. The DWT scheme works on Haar coefficients, whose LL
+// is the sum of each 2x2 area.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Wavelet {
+    #[default]
+    Haar,
+    // The reversible integer 5/3 lifting of JPEG 2000, with symmetric extension at the edges.
+    // Its coefficients stay on the scale of the samples.
+    LeGall53,
+}
+
+// The four sub-bands of a one level transform as planes, with Haar the same coefficients as
+// `DWTImage`. An odd last row or column of the image is dropped by the forward transform and
+// comes back as zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanarDWT {
+    pub ll: Planes<i32>,
+    pub lh: Planes<i32>,
+    pub hl: Planes<i32>,
+    pub hh: Planes<i32>,
+    pub orig_width: usize,
+    pub orig_height: usize,
+    pub wavelet: Wavelet,
+}
+
+impl PlanarDWT {
+    pub fn forward(img: &Planes<i32>) -> PlanarDWT {
+        return PlanarDWT::forward_in(img, Wavelet::Haar);
+    }
+
+    pub fn forward_in(img: &Planes<i32>, wavelet: Wavelet) -> PlanarDWT {
+        let (width, height) = (img.width / 2, img.height / 2);
+        let channels = img.planes.len();
+        let mut bands = [(); 4].map(|_| Planes::new(width, height, channels));
+
+        for c in 0..channels {
+            let [ll, lh, hl, hh] = &mut bands;
+            let bands = [
+                &mut ll.planes[c][..],
+                &mut lh.planes[c][..],
+                &mut hl.planes[c][..],
+                &mut hh.planes[c][..],
+            ];
+            match wavelet {
+                Wavelet::Haar => haar_forward(&img.planes[c], img.width, bands),
+                Wavelet::LeGall53 => legall_forward(&img.planes[c], img.width, bands),
+            }
+        }
+
+        let [ll, lh, hl, hh] = bands;
+        return PlanarDWT {
+            ll,
+            lh,
+            hl,
+            hh,
+            orig_width: img.width,
+            orig_height: img.height,
+            wavelet,
+        };
+    }
+
+    pub fn inverse(&self) -> Planes<f64> {
+        let channels = self.ll.planes.len();
+        let mut img = Planes::new(self.orig_width, self.orig_height, channels);
+
+        for c in 0..channels {
+            let bands = [
+                &self.ll.planes[c][..],
+                &self.lh.planes[c][..],
+                &self.hl.planes[c][..],
+                &self.hh.planes[c][..],
+            ];
+            let plane = &mut img.planes[c];
+            match self.wavelet {
+                Wavelet::Haar => haar_inverse(bands, self.ll.width, plane, self.orig_width),
+                Wavelet::LeGall53 => legall_inverse(bands, self.ll.width, plane, self.orig_width),
+            }
+        }
+        return img;
+    }
+
+    pub fn from_dwt_image<P: Pixel<Sample = i32>>(dwt: &DWTImage<P>) -> PlanarDWT {
+        let (width, height) = (dwt.orig_width / 2, dwt.orig_height / 2);
+        return PlanarDWT {
+            ll: Planes::from_pixels(&dwt.ll, width, height),
+            lh: Planes::from_pixels(&dwt.lh, width, height),
+            hl: Planes::from_pixels(&dwt.hl, width, height),
+            hh: Planes::from_pixels(&dwt.hh, width, height),
+            orig_width: dwt.orig_width,
+            orig_height: dwt.orig_height,
+            wavelet: Wavelet::Haar,
+        };
+    }
+
+    // max_val and color space are left to the caller
+    pub fn to_dwt_image<P: Pixel<Sample = i32>>(&self) -> DWTImage<P> {
+        return DWTImage::new(
+            self.ll.to_pixels(),
+            self.lh.to_pixels(),
+            self.hl.to_pixels(),
+            self.hh.to_pixels(),
+            self.orig_width,
+            self.orig_height,
+        );
+    }
+}
+
+// Rows 2y and 2y + 1 into row y of each sub-band. With s and d the sum and difference of the two
+// rows, LL and HL are the sum and difference of neighbouring s, LH and HH those of d.
+fn haar_forward(plane: &[i32], plane_width: usize, [ll, lh, hl, hh]: [&mut [i32]; 4]) {
+    let width = plane_width / 2;
+    for y in 0..ll.len() / width.max(1) {
+        let top = &plane[2 * y * plane_width..][..2 * width];
+        let bottom = &plane[(2 * y + 1) * plane_width..][..2 * width];
+        for x in 0..width {
+            let (a, b, c, d) = (top[2 * x], top[2 * x + 1], bottom[2 * x], bottom[2 * x + 1]);
+            let i = y * width + x;
+            ll[i] = (a + b) + (c + d);
+            lh[i] = (a + b) - (c + d);
+            hl[i] = (a - b) + (c - d);
+            hh[i] = (a - b) - (c - d);
+        }
+    }
+}
+
+// Undoes `haar_forward`, in f64 since replaced coefficients no longer add up to even sums
+fn haar_inverse(
+    [ll, lh, hl, hh]: [&[i32]; 4],
+    width: usize,
+    plane: &mut [f64],
+    plane_width: usize,
+) {
+    for y in 0..ll.len() / width.max(1) {
+        let (above, below) = plane.split_at_mut((2 * y + 1) * plane_width);
+        let top = &mut above[2 * y * plane_width..][..2 * width];
+        let bottom = &mut below[..2 * width];
+        for x in 0..width {
+            let i = y * width + x;
+            let (ll, lh, hl, hh) = (ll[i] as f64, lh[i] as f64, hl[i] as f64, hh[i] as f64);
+            let low_top = (ll + lh) / 2.0;
+            let low_bottom = ll - low_top;
+            let high_top = (hl + hh) / 2.0;
+            let high_bottom = hl - high_top;
+
+            top[2 * x] = (low_top + high_top) / 2.0;
+            top[2 * x + 1] = low_top - top[2 * x];
+            bottom[2 * x] = (low_bottom + high_bottom) / 2.0;
+            bottom[2 * x + 1] = low_bottom - bottom[2 * x];
+        }
+    }
+}
+
+// Rows first into a low and a high half, then the columns of both halves
+fn legall_forward(plane: &[i32], plane_width: usize, [ll, lh, hl, hh]: [&mut [i32]; 4]) {
+    let width = plane_width / 2;
+    let height = ll.len() / width.max(1);
+    let (mut low, mut high) = (vec![0; width * 2 * height], vec![0; width * 2 * height]);
+    for y in 0..2 * height {
+        let row = y * width..(y + 1) * width;
+        let samples = &plane[y * plane_width..][..2 * width];
+        lift_row_forward(samples, &mut low[row.clone()], &mut high[row]);
+    }
+    lift_forward(&low, width, ll, lh);
+    lift_forward(&high, width, hl, hh);
+}
+
+// Undoes `legall_forward` exactly, whatever the coefficients
+fn legall_inverse(
+    [ll, lh, hl, hh]: [&[i32]; 4],
+    width: usize,
+    plane: &mut [f64],
+    plane_width: usize,
+) {
+    let height = ll.len() / width.max(1);
+    let (mut low, mut high) = (vec![0; width * 2 * height], vec![0; width * 2 * height]);
+    lift_inverse(ll, lh, width, &mut low);
+    lift_inverse(hl, hh, width, &mut high);
+    let mut samples = vec![0; 2 * width];
+    for y in 0..2 * height {
+        let row = y * width..(y + 1) * width;
+        lift_row_inverse(&low[row.clone()], &high[row], &mut samples);
+        for (pixel, &sample) in plane[y * plane_width..].iter_mut().zip(&samples) {
+            *pixel = sample as f64;
+        }
+    }
+}
+
+// One 5/3 lifting step along a row of 2n samples into n low and n high coefficients
+fn lift_row_forward(samples: &[i32], low: &mut [i32], high: &mut [i32]) {
+    let n = low.len();
+    for i in 0..n {
+        // the sample past the end mirrors the one before it
+        let next = samples[if i + 1 < n { 2 * i + 2 } else { 2 * i }];
+        high[i] = samples[2 * i + 1] - ((samples[2 * i] + next) >> 1);
+    }
+    for i in 0..n {
+        low[i] = samples[2 * i] + ((high[i.saturating_sub(1)] + high[i] + 2) >> 2);
+    }
+}
+
+fn lift_row_inverse(low: &[i32], high: &[i32], samples: &mut [i32]) {
+    let n = low.len();
+    for i in 0..n {
+        samples[2 * i] = low[i] - ((high[i.saturating_sub(1)] + high[i] + 2) >> 2);
+    }
+    for i in 0..n {
+        let next = samples[if i + 1 < n { 2 * i + 2 } else { 2 * i }];
+        samples[2 * i + 1] = high[i] + ((samples[2 * i] + next) >> 1);
+    }
+}
+
+// `lift_row_forward` down the columns, along a sequence of 2n rows of `width` samples into n low
+// and n high rows, a whole row at a time
+fn lift_forward(samples: &[i32], width: usize, low: &mut [i32], high: &mut [i32]) {
+    let lines = low.len() / width.max(1);
+    let at = |line: usize, x: usize| samples[line * width + x];
+    for i in 0..lines {
+        // the line past the end mirrors the one before it
+        let next = if i + 1 < lines { 2 * i + 2 } else { 2 * i };
+        for x in 0..width {
+            high[i * width + x] = at(2 * i + 1, x) - ((at(2 * i, x) + at(next, x)) >> 1);
+        }
+    }
+    for i in 0..lines {
+        let previous = i.saturating_sub(1);
+        for x in 0..width {
+            let (left, right) = (high[previous * width + x], high[i * width + x]);
+            low[i * width + x] = at(2 * i, x) + ((left + right + 2) >> 2);
+        }
+    }
+}
+
+fn lift_inverse(low: &[i32], high: &[i32], width: usize, samples: &mut [i32]) {
+    let lines = low.len() / width.max(1);
+    for i in 0..lines {
+        let previous = i.saturating_sub(1);
+        for x in 0..width {
+            let (left, right) = (high[previous * width + x], high[i * width + x]);
+            samples[2 * i * width + x] = low[i * width + x] - ((left + right + 2) >> 2);
+        }
+    }
+    for i in 0..lines {
+        let next = if i + 1 < lines { 2 * i + 2 } else { 2 * i };
+        for x in 0..width {
+            let (even, following) = (samples[2 * i * width + x], samples[next * width + x]);
+            samples[(2 * i + 1) * width + x] = high[i * width + x] + ((even + following) >> 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::RGBColor;
+
+    fn planes(width: usize, height: usize, channels: usize) -> Planes<i32> {
+        let mut img = Planes::new(width, height, channels);
+        for (c, plane) in img.planes.iter_mut().enumerate() {
+            for (i, sample) in plane.iter_mut().enumerate() {
+                *sample = ((i * 31 + c * 7) % 256) as i32 - (i % 3) as i32 * 40;
+            }
+        }
+        return img;
+    }
+
+    #[test]
+    fn inverse_undoes_forward() {
+        for wavelet in [Wavelet::Haar, Wavelet::LeGall53] {
+            for (width, height, channels) in [(12, 10, 3), (2, 2, 1), (16, 4, 4), (6, 14, 3)] {
+                let img = planes(width, height, channels);
+                let back = PlanarDWT::forward_in(&img, wavelet).inverse();
+                for (plane, expected) in back.planes.iter().zip(&img.planes) {
+                    let expected: Vec<f64> = expected.iter().map(|&sample| sample as f64).collect();
+                    assert_eq!(plane, &expected, "{:?} {}x{}", wavelet, width, height);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_sizes_lose_the_last_row_and_column() {
+        let img = planes(7, 5, 1);
+        let back = PlanarDWT::forward_in(&img, Wavelet::LeGall53).inverse();
+        for y in 0..5 {
+            for x in 0..7 {
+                let expected = if x == 6 || y == 4 {
+                    0.0
+                } else {
+                    img.planes[0][y * 7 + x] as f64
+                };
+                assert_eq!(back.planes[0][y * 7 + x], expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn haar_matches_the_pixel_by_pixel_transform() {
+        let mut img = PPMImage::<RGBColor<i32>>::new();
+        (img.width, img.height, img.max_val) = (9, 6, 255);
+        img.data = (0..54)
+            .map(|i| RGBColor::new(i * 7 % 256, i * 13 % 251, i * i % 241))
+            .collect();
+        let dwt = DWTImage::<RGBColor<i32>>::from_ppm(&img);
+        let planar = PlanarDWT::forward(&Planes::from_image(&img));
+        let planar_dwt = planar.to_dwt_image::<RGBColor<i32>>();
+        assert_eq!(
+            [
+                &planar_dwt.ll,
+                &planar_dwt.lh,
+                &planar_dwt.hl,
+                &planar_dwt.hh
+            ],
+            [&dwt.ll, &dwt.lh, &dwt.hl, &dwt.hh]
+        );
+        let back: Vec<RGBColor<f64>> = planar.inverse().to_pixels();
+        assert_eq!(back, dwt.inverse_dwt_f64().data);
+    }
+
+    #[test]
+    fn legall_keeps_smooth_areas_out_of_the_details() {
+        // a linear ramp is predicted exactly, away from the mirrored edges
+        let mut img = Planes::new(16, 16, 1);
+        for (i, sample) in img.planes[0].iter_mut().enumerate() {
+            *sample = (i % 16 + 2 * (i / 16)) as i32;
+        }
+        let dwt = PlanarDWT::forward_in(&img, Wavelet::LeGall53);
+        for y in 0..7 {
+            for x in 0..7 {
+                assert_eq!(dwt.lh.planes[0][y * 8 + x], 0);
+                assert_eq!(dwt.hl.planes[0][y * 8 + x], 0);
+                assert_eq!(dwt.hh.planes[0][y * 8 + x], 0);
+            }
+        }
+        // with no details to add back, LL is the ramp at the top left of each 2x2 area
+        assert_eq!(dwt.ll.planes[0][3 * 8 + 2], 4 + 2 * 6);
+    }
+}
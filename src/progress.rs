@@ -0,0 +1,153 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Every stage reports about this many times at most, however large its total
+const REPORTS_PER_STAGE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Transform,
+    Matching,
+    BlockDifferences,
+    BlockReplacement,
+    InverseTransform,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Stage::Transform => "transform",
+            Stage::Matching => "matching",
+            Stage::BlockDifferences => "block differences",
+            Stage::BlockReplacement => "block replacement",
+            Stage::InverseTransform => "inverse transform",
+        };
+    }
+}
+
+// Told `done` out of `total` units of `stage`, from the thread doing the work. Any
+// `Fn(Stage, usize, usize)` closure is one.
+pub trait Progress: Send + Sync {
+    fn report(&self, stage: Stage, done: usize, total: usize);
+}
+
+impl<F: Fn(Stage, usize, usize) + Send + Sync> Progress for F {
+    fn report(&self, stage: Stage, done: usize, total: usize) {
+        self(stage, done, total);
+    }
+}
+
+// Shared flag another thread sets to stop a running operation, clones see the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        return CancellationToken::default();
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::Relaxed);
+    }
+}
+
+// What long operations report their progress to and check for cancellation, the default one
+// does neither
+#[derive(Clone, Default)]
+pub struct Monitor {
+    progress: Option<Arc<dyn Progress>>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Monitor")
+            .field("progress", &self.progress.is_some())
+            .field("cancellation", &self.cancellation)
+            .finish();
+    }
+}
+
+impl Monitor {
+    pub fn with_progress<T: Progress + 'static>(self, progress: T) -> Monitor {
+        return Monitor {
+            progress: Some(Arc::new(progress)),
+            ..self
+        };
+    }
+
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> Monitor {
+        return Monitor {
+            cancellation: Some(cancellation),
+            ..self
+        };
+    }
+
+    // Called for every unit of work, `done` going from 0 to `total`. The first and last units
+    // and every thousandth of the way are reported, and an `Interrupted` error is returned once
+    // the token is cancelled for the operation to stop with.
+    pub fn step(&self, stage: Stage, done: usize, total: usize) -> std::io::Result<()> {
+        if let Some(progress) = &self.progress {
+            let stride = (total / REPORTS_PER_STAGE).max(1);
+            if done.is_multiple_of(stride) || done == total {
+                progress.report(stage, done, total);
+            }
+        }
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(Error::new(
+                ErrorKind::Interrupted,
+                format!("Cancelled during {}", stage.name()),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports_are_spread_over_the_stage() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let monitor = Monitor::default().with_progress(move |stage, done, total| {
+            sink.lock().unwrap().push((stage, done, total));
+        });
+        for done in 0..=10_000 {
+            monitor.step(Stage::Matching, done, 10_000).unwrap();
+        }
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), REPORTS_PER_STAGE + 1);
+        assert_eq!(reports[0], (Stage::Matching, 0, 10_000));
+        assert_eq!(
+            reports[REPORTS_PER_STAGE],
+            (Stage::Matching, 10_000, 10_000)
+        );
+    }
+
+    #[test]
+    fn cancelled_steps_are_errors() {
+        let token = CancellationToken::new();
+        let monitor = Monitor::default().with_cancellation(token.clone());
+        assert!(monitor.step(Stage::Transform, 0, 1).is_ok());
+        token.cancel();
+        let err = monitor.step(Stage::Transform, 1, 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::{Error, ErrorKind};
+
+use crate::algorithm::{self, StegoAlgorithm, StegoKey, BITS_PER_SECRET_PIXEL};
+use crate::image::PPMImage;
+use crate::pixel::Pixel;
+use crate::random::SplitMix64;
+
+// Pixel value differencing (Wu & Tsai) on horizontal pairs of every channel: the difference of
+// a pair falls in a range of the table, the range width sets how many bits it carries and the
+// difference is replaced by the lower bound of the range plus those bits. Smooth areas get few
+// bits, edges many.
+// The new pair keeps floor((p1 + p2) / 2), so embedder and extractor agree on which pairs could
+// leave 0..=max_val and are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct PVDStegano {
+    pub password: Option<String>,
+}
+
+impl PVDStegano {
+    pub fn new(password: Option<String>) -> PVDStegano {
+        return PVDStegano { password };
+    }
+
+    // Sample index of the first pixel of every pair (pixel * 3 + channel), in embedding order
+    fn pair_order(&self, img: &PPMImage) -> Vec<usize> {
+        let mut order = Vec::with_capacity(img.data.len() * 3 / 2);
+        for y in 0..img.height {
+            for x in (0..img.width.saturating_sub(1)).step_by(2) {
+                for c in 0..3 {
+                    order.push((y * img.width + x) * 3 + c);
+                }
+            }
+        }
+        if let Some(password) = &self.password {
+            SplitMix64::from_password(password).shuffle(&mut order);
+        }
+        return order;
+    }
+
+    // Number of bits the cover can carry, the exact value since ranges and pair averages survive
+    // embedding
+    pub fn capacity_bits(&self, cover: &PPMImage) -> usize {
+        let ranges = range_table(cover.max_val);
+        let mut bits = 0;
+        for sample in self.pair_order(cover) {
+            let (p1, p2) = read_pair(cover, sample);
+            if let Some(range) = usable_range(&ranges, p1, p2, cover.max_val as i32) {
+                bits += range.bits;
+            }
+        }
+        return bits;
+    }
+}
+
+impl StegoAlgorithm for PVDStegano {
+    fn embed(&self, cover: &PPMImage, secret: &PPMImage) -> std::io::Result<(PPMImage, StegoKey)> {
+        let payload = algorithm::secret_to_bytes(secret);
+        let total_bits = payload.len() * 8;
+        let ranges = range_table(cover.max_val);
+        let max_val = cover.max_val as i32;
+        let mut stego = PPMImage {
+            img_type: String::from("P6"),
+            width: cover.width,
+            height: cover.height,
+            max_val: cover.max_val,
+            data: cover.data.clone(),
+        };
+        let mut bit_index = 0;
+
+        for sample in self.pair_order(cover) {
+            if bit_index >= total_bits {
+                break;
+            }
+            let (p1, p2) = read_pair(cover, sample);
+            let range = match usable_range(&ranges, p1, p2, max_val) {
+                Some(range) => range,
+                None => continue,
+            };
+
+            let mut value = 0;
+            for _ in 0..range.bits {
+                value <<= 1;
+                if bit_index < total_bits {
+                    value |= algorithm::bit_of(&payload, bit_index) as i32;
+                }
+                bit_index += 1;
+            }
+            let difference = (range.lower + value) * if p2 >= p1 { 1 } else { -1 };
+            let (q1, q2) = new_pair(average(p1, p2), difference);
+            *stego.data[sample / 3].channel_mut(sample % 3) = q1;
+            *stego.data[sample / 3 + 1].channel_mut(sample % 3) = q2;
+        }
+
+        if bit_index < total_bits {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Secret image is too large for this cover",
+            ));
+        }
+        return Ok((stego, StegoKey::new(secret.width, secret.height)));
+    }
+
+    fn extract(&self, stego: &PPMImage, key: &StegoKey) -> std::io::Result<PPMImage> {
+        // PVD hides at most 7 bits per pair of samples, below one secret pixel per cover pixel
+        let pixels = algorithm::secret_pixels(key, stego.data.len())?;
+        let mut payload = vec![0_u8; pixels * 3];
+        let total_bits = payload.len() * 8;
+        let ranges = range_table(stego.max_val);
+        let mut bit_index = 0;
+
+        for sample in self.pair_order(stego) {
+            if bit_index >= total_bits {
+                break;
+            }
+            let (p1, p2) = read_pair(stego, sample);
+            let range = match usable_range(&ranges, p1, p2, stego.max_val as i32) {
+                Some(range) => range,
+                None => continue,
+            };
+
+            let value = (p2 - p1).abs() - range.lower;
+            for shift in (0..range.bits).rev() {
+                if bit_index < total_bits {
+                    algorithm::set_bit(&mut payload, bit_index, (value >> shift) as u8);
+                }
+                bit_index += 1;
+            }
+        }
+
+        if bit_index < total_bits {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Stego key asks for more pixels than the image can carry",
+            ));
+        }
+        return Ok(algorithm::secret_from_bytes(
+            key.width, key.height, &payload,
+        ));
+    }
+
+    fn capacity(&self, cover: &PPMImage) -> usize {
+        return self.capacity_bits(cover) / BITS_PER_SECRET_PIXEL;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    lower: i32,
+    upper: i32,
+    bits: usize,
+}
+
+// Wu & Tsai table for 8 bit samples: [0, 7], [8, 15], [16, 31], [32, 63], [64, 127], [128, 255],
+// keeps doubling for deeper samples, a last partial range carries floor(log2(width)) bits
+fn range_table(max_val: usize) -> Vec<Range> {
+    let mut ranges = Vec::new();
+    let mut lower = 0;
+    while lower <= max_val as i32 {
+        let width = if lower < 16 { 8 } else { lower };
+        let upper = (lower + width - 1).min(max_val as i32);
+        ranges.push(Range {
+            lower,
+            upper,
+            bits: (upper - lower + 1).ilog2() as usize,
+        });
+        lower += width;
+    }
+    return ranges;
+}
+
+fn read_pair(img: &PPMImage, sample: usize) -> (i32, i32) {
+    return (
+        img.data[sample / 3].channel(sample % 3),
+        img.data[sample / 3 + 1].channel(sample % 3),
+    );
+}
+
+fn average(p1: i32, p2: i32) -> i32 {
+    return (p1 + p2).div_euclid(2);
+}
+
+fn new_pair(average: i32, difference: i32) -> (i32, i32) {
+    let first = average - difference.div_euclid(2);
+    return (first, first + difference);
+}
+
+// Range of the pair if both ends of the range keep it inside 0..=max_val. Both signs are tried,
+// a zero difference does not keep the sign it was embedded with.
+fn usable_range(ranges: &[Range], p1: i32, p2: i32, max_val: i32) -> Option<Range> {
+    let difference = (p2 - p1).abs();
+    let range = *ranges
+        .iter()
+        .find(|range| range.lower <= difference && difference <= range.upper)?;
+    let center = average(p1, p2);
+
+    for bound in [range.lower, range.upper, -range.lower, -range.upper] {
+        let (q1, q2) = new_pair(center, bound);
+        if q1 < 0 || q2 < 0 || q1 > max_val || q2 > max_val {
+            return None;
+        }
+    }
+    return Some(range);
+}
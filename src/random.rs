@@ -0,0 +1,66 @@
+use crate::color::RGBColor;
+
+// FNV-1a, std's `DefaultHasher` is not guaranteed to be stable between releases
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Fnv1a {
+        return Fnv1a(0xcbf29ce484222325);
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn write_color(&mut self, color: &RGBColor<i32>) {
+        for value in [color.red, color.green, color.blue] {
+            self.write(&value.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        return self.0;
+    }
+}
+
+// Small deterministic generator, the same password has to give the same sequence everywhere
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> SplitMix64 {
+        return SplitMix64(seed);
+    }
+
+    pub(crate) fn from_password(password: &str) -> SplitMix64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write(password.as_bytes());
+        return SplitMix64::new(hasher.finish());
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        return z ^ (z >> 31);
+    }
+
+    // Uniform in 0..bound
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        return ((self.next_u64() as u128 * bound as u128) >> 64) as usize;
+    }
+
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
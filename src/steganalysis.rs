@@ -0,0 +1,544 @@
+use crate::color::RGBColor;
+use crate::image::PPMImage;
+use crate::stegano::DWTImage;
+
+const WAVELET_LEVELS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct SteganalysisReport {
+    pub chi_square: f64,    // probability that LSB pairs were equalized by embedding
+    pub rs: f64,            // estimated fraction of pixels carrying a message (RS analysis)
+    pub sample_pair: f64,   // estimated fraction of pixels carrying a message (SPA)
+    pub wavelet: f64,       // how far the finest detail bands moved towards gaussian noise
+    pub features: Vec<f64>, // Farid-style wavelet statistics, for an external classifier
+    pub score: f64,         // overall suspicion in 0..=1
+}
+
+impl SteganalysisReport {
+    pub fn from_ppm(img: &PPMImage) -> SteganalysisReport {
+        let chi_square = chi_square_attack(img);
+        let rs = rs_analysis(img);
+        let sample_pair = sample_pair_analysis(img);
+        let (features, wavelet) = wavelet_statistics(img);
+
+        // An image is as suspicious as the most confident detector
+        let score = [chi_square, rs, sample_pair, wavelet]
+            .iter()
+            .fold(0.0_f64, |acc, value| acc.max(*value));
+
+        return SteganalysisReport {
+            chi_square,
+            rs,
+            sample_pair,
+            wavelet,
+            features,
+            score,
+        };
+    }
+}
+
+// Westfeld & Pfitzmann: LSB replacement equalizes the histogram bins 2k and 2k + 1
+pub fn chi_square_attack(img: &PPMImage) -> f64 {
+    let mut histogram = vec![0_u64; img.max_val + 2];
+    for plane in channel_planes(img) {
+        for value in plane {
+            histogram[value.clamp(0, img.max_val as i32) as usize] += 1;
+        }
+    }
+
+    let (mut chi, mut categories) = (0.0, 0);
+    for k in 0..histogram.len() / 2 {
+        let expected = (histogram[2 * k] + histogram[2 * k + 1]) as f64 / 2.0;
+        if expected < 1.0 {
+            continue;
+        }
+        chi += (histogram[2 * k] as f64 - expected).powf(2.0) / expected;
+        categories += 1;
+    }
+
+    if categories < 2 {
+        return 0.0;
+    }
+    return 1.0 - regularized_gamma_p((categories - 1) as f64 / 2.0, chi / 2.0);
+}
+
+// Fridrich, Goljan & Du, groups of 4 horizontal neighbours with the mask [0, 1, 1, 0]
+pub fn rs_analysis(img: &PPMImage) -> f64 {
+    let mut estimates = Vec::new();
+
+    for plane in channel_planes(img) {
+        let flipped: Vec<i32> = plane.iter().map(|value| value ^ 1).collect();
+        let (r_m, s_m, r_neg_m, s_neg_m) = rs_counts(&plane, img.width, img.height);
+        let (r_m1, s_m1, r_neg_m1, s_neg_m1) = rs_counts(&flipped, img.width, img.height);
+
+        let (d0, d1) = (r_m - s_m, r_m1 - s_m1);
+        let (d_neg0, d_neg1) = (r_neg_m - s_neg_m, r_neg_m1 - s_neg_m1);
+        let a = 2.0 * (d1 + d0);
+        let b = d_neg0 - d_neg1 - d1 - 3.0 * d0;
+        let c = d0 - d_neg0;
+
+        let z = match smallest_root(a, b, c) {
+            Some(z) => z,
+            None => continue,
+        };
+        if (z - 0.5).abs() > f64::EPSILON {
+            estimates.push((z / (z - 0.5)).clamp(0.0, 1.0));
+        }
+    }
+
+    return mean(&estimates);
+}
+
+// Dumitrescu, Wu & Wang, on horizontally adjacent sample pairs
+pub fn sample_pair_analysis(img: &PPMImage) -> f64 {
+    let mut estimates = Vec::new();
+
+    for plane in channel_planes(img) {
+        let (mut x, mut y, mut k, mut pairs) = (0.0, 0.0, 0.0, 0.0);
+        for row in 0..img.height {
+            for col in 0..img.width.saturating_sub(1) {
+                let u = plane[row * img.width + col];
+                let v = plane[row * img.width + col + 1];
+                let v_even = v % 2 == 0;
+                if (v_even && u < v) || (!v_even && u > v) {
+                    x += 1.0;
+                }
+                if (v_even && u > v) || (!v_even && u < v) {
+                    y += 1.0;
+                }
+                if u >> 1 == v >> 1 {
+                    k += 1.0;
+                }
+                pairs += 1.0;
+            }
+        }
+
+        if k == 0.0 {
+            continue;
+        }
+        // the root is the fraction of samples that were changed, half of the carriers
+        if let Some(changed) = smallest_root(2.0 * k, 2.0 * (2.0 * x - pairs), y - x) {
+            estimates.push((2.0 * changed).clamp(0.0, 1.0));
+        }
+    }
+
+    return mean(&estimates);
+}
+
+// Farid: mean, variance, skewness and kurtosis of every detail sub-band of a multi level
+// decomposition, plus the same statistics of the log error of a linear predictor of each
+// coefficient magnitude from its left and upper neighbours and its parent.
+// The returned score compares the kurtosis of the finest and the next level, natural images
+// are heavy tailed at every scale while additive embedding noise is not.
+pub fn wavelet_statistics(img: &PPMImage) -> (Vec<f64>, f64) {
+    let mut levels = Vec::<DWTImage>::new();
+    let mut current = DWTImage::from_ppm(img);
+
+    while levels.len() < WAVELET_LEVELS && current.orig_width >= 8 && current.orig_height >= 8 {
+        let approximation = PPMImage {
+            img_type: String::from("P6"),
+            width: current.orig_width / 2,
+            height: current.orig_height / 2,
+            max_val: img.max_val * 4,
+            data: current.ll.clone(),
+        };
+        let next = DWTImage::from_ppm(&approximation);
+        levels.push(current);
+        current = next;
+    }
+    if levels.is_empty() {
+        return (Vec::new(), 0.0);
+    }
+
+    let mut features = Vec::new();
+    let mut kurtosis = vec![Vec::<f64>::new(); levels.len()];
+
+    for (level, dwt) in levels.iter().enumerate() {
+        for band in [&dwt.lh, &dwt.hl, &dwt.hh] {
+            for plane in planes_of(band) {
+                let statistics = moments(&plane);
+                kurtosis[level].push(statistics[3]);
+                features.extend_from_slice(&statistics);
+            }
+        }
+    }
+
+    for level in 0..levels.len() - 1 {
+        let (dwt, parent) = (&levels[level], &levels[level + 1]);
+        let (width, height) = (dwt.orig_width / 2, dwt.orig_height / 2);
+        for (band, parent_band) in [
+            (&dwt.lh, &parent.lh),
+            (&dwt.hl, &parent.hl),
+            (&dwt.hh, &parent.hh),
+        ] {
+            let parent_planes = planes_of(parent_band);
+            for (c, plane) in planes_of(band).iter().enumerate() {
+                let errors = prediction_errors(plane, &parent_planes[c], width, height);
+                features.extend_from_slice(&moments(&errors));
+            }
+        }
+    }
+
+    let score = if kurtosis.len() < 2 {
+        0.0
+    } else {
+        let (finest, coarser) = (mean(&kurtosis[0]), mean(&kurtosis[1]));
+        if coarser <= 3.0 {
+            0.0
+        } else {
+            ((coarser - finest) / (coarser - 3.0)).clamp(0.0, 1.0)
+        }
+    };
+
+    return (features, score);
+}
+
+fn rs_counts(plane: &[i32], width: usize, height: usize) -> (f64, f64, f64, f64) {
+    const MASK: [bool; 4] = [false, true, true, false];
+    let (mut r_m, mut s_m, mut r_neg_m, mut s_neg_m, mut groups) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    for y in 0..height {
+        for x in (0..width.saturating_sub(3)).step_by(4) {
+            let group = [0, 1, 2, 3].map(|i| plane[y * width + x + i]);
+            let positive = [0, 1, 2, 3].map(|i| if MASK[i] { group[i] ^ 1 } else { group[i] });
+            let negative = [0, 1, 2, 3].map(|i| {
+                if MASK[i] {
+                    ((group[i] + 1) ^ 1) - 1
+                } else {
+                    group[i]
+                }
+            });
+
+            let f0 = smoothness(&group);
+            let (f_positive, f_negative) = (smoothness(&positive), smoothness(&negative));
+            if f_positive > f0 {
+                r_m += 1.0;
+            } else if f_positive < f0 {
+                s_m += 1.0;
+            }
+            if f_negative > f0 {
+                r_neg_m += 1.0;
+            } else if f_negative < f0 {
+                s_neg_m += 1.0;
+            }
+            groups += 1.0;
+        }
+    }
+
+    if groups == 0.0 {
+        return (0.0, 0.0, 0.0, 0.0);
+    }
+    return (
+        r_m / groups,
+        s_m / groups,
+        r_neg_m / groups,
+        s_neg_m / groups,
+    );
+}
+
+fn smoothness(group: &[i32; 4]) -> i32 {
+    return (0..3).map(|i| (group[i + 1] - group[i]).abs()).sum();
+}
+
+// log2 |c| - log2 |w . q| where q are the magnitudes of the left, upper and parent coefficients
+// and w is the least squares fit over the whole band
+fn prediction_errors(plane: &[f64], parent: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let parent_width = width / 2;
+    let mut rows = Vec::<([f64; 3], f64)>::new();
+
+    for y in 1..(height / 2) * 2 {
+        for x in 1..(width / 2) * 2 {
+            let q = [
+                plane[y * width + x - 1].abs(),
+                plane[(y - 1) * width + x].abs(),
+                parent[(y / 2) * parent_width + x / 2].abs(),
+            ];
+            rows.push((q, plane[y * width + x].abs()));
+        }
+    }
+
+    let mut normal = [[0.0; 3]; 3];
+    let mut rhs = [0.0; 3];
+    for (q, target) in &rows {
+        for i in 0..3 {
+            for j in 0..3 {
+                normal[i][j] += q[i] * q[j];
+            }
+            rhs[i] += q[i] * target;
+        }
+    }
+    let weights = solve_3x3(normal, rhs).unwrap_or([0.0; 3]);
+
+    return rows
+        .iter()
+        .map(|(q, target)| {
+            let predicted = weights[0] * q[0] + weights[1] * q[1] + weights[2] * q[2];
+            (target + 1.0).log2() - (predicted.abs() + 1.0).log2()
+        })
+        .collect();
+}
+
+// elimination reads two rows of `a` at once, indexes are clearer than iterators
+#[allow(clippy::needless_range_loop)]
+fn solve_3x3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..3 {
+            let factor = a[row][col] / a[col][col];
+            for k in col..3 {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let sum: f64 = (row + 1..3).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    return Some(x);
+}
+
+// Mean, variance, skewness and (non excess) kurtosis
+fn moments(values: &[f64]) -> [f64; 4] {
+    if values.is_empty() {
+        return [0.0; 4];
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let (mut m2, mut m3, mut m4) = (0.0, 0.0, 0.0);
+    for value in values {
+        let d = value - mean;
+        m2 += d * d;
+        m3 += d * d * d;
+        m4 += d * d * d * d;
+    }
+    let (m2, m3, m4) = (m2 / n, m3 / n, m4 / n);
+    if m2 == 0.0 {
+        return [mean, 0.0, 0.0, 0.0];
+    }
+
+    return [mean, m2, m3 / m2.powf(1.5), m4 / (m2 * m2)];
+}
+
+fn smallest_root(a: f64, b: f64, c: f64) -> Option<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        return Some(-c / b);
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let (r1, r2) = (
+        (-b + discriminant.sqrt()) / (2.0 * a),
+        (-b - discriminant.sqrt()) / (2.0 * a),
+    );
+    return Some(if r1.abs() < r2.abs() { r1 } else { r2 });
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    return values.iter().sum::<f64>() / values.len() as f64;
+}
+
+fn channel_planes(img: &PPMImage) -> [Vec<i32>; 3] {
+    return [
+        img.data.iter().map(|color| color.red).collect(),
+        img.data.iter().map(|color| color.green).collect(),
+        img.data.iter().map(|color| color.blue).collect(),
+    ];
+}
+
+fn planes_of(band: &[RGBColor<i32>]) -> [Vec<f64>; 3] {
+    return [
+        band.iter().map(|color| color.red as f64).collect(),
+        band.iter().map(|color| color.green as f64).collect(),
+        band.iter().map(|color| color.blue as f64).collect(),
+    ];
+}
+
+// P(a, x) from Numerical Recipes, series below a + 1 and continued fraction above
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let log_prefix = a * x.ln() - x - ln_gamma(a);
+
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-14 {
+                break;
+            }
+        }
+        return (sum * log_prefix.exp()).min(1.0);
+    }
+
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..500 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-14 {
+            break;
+        }
+    }
+    return (1.0 - log_prefix.exp() * h).max(0.0);
+}
+
+// Lanczos approximation, g = 7
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (x + i as f64);
+    }
+    let t = x + 7.5;
+    return 0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::Pixel;
+    use crate::random::SplitMix64;
+
+    // Flat areas with sharp borders, gentle gradients and a little sensor noise, the detail
+    // bands of such images are heavy tailed like those of photographs
+    fn cover() -> PPMImage {
+        let mut rng = SplitMix64::new(7);
+        let levels: Vec<i32> = (0..16 * 16 * 3)
+            .map(|_| 40 + rng.below(160) as i32)
+            .collect();
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (256, 256, 255);
+        for y in 0..256 {
+            for x in 0..256 {
+                let tile = (y / 23 * 16 + x / 21) * 3;
+                let mut color = RGBColor::default();
+                for c in 0..3 {
+                    let gradient = (x % 21 + y % 23) as i32 / 4;
+                    let noise = rng.below(3) as i32 - 1;
+                    *color.channel_mut(c) = levels[tile + c] + gradient + noise;
+                }
+                img.data.push(color);
+            }
+        }
+        return img;
+    }
+
+    // LSB replacement of a random message bit in `rate` of the samples
+    fn embed(cover: &PPMImage, rate: f64) -> PPMImage {
+        let mut rng = SplitMix64::new(11);
+        let mut stego = cover.clone();
+        for color in stego.data.iter_mut() {
+            for c in 0..3 {
+                if (rng.next_u64() as f64) < rate * u64::MAX as f64 {
+                    *color.channel_mut(c) = color.channel(c) & !1 | (rng.next_u64() & 1) as i32;
+                }
+            }
+        }
+        return stego;
+    }
+
+    // mean kurtosis of the finest detail bands, the first 9 sets of moments
+    fn finest_kurtosis(img: &PPMImage) -> f64 {
+        let (features, _) = wavelet_statistics(img);
+        return mean(&(0..9).map(|i| features[4 * i + 3]).collect::<Vec<f64>>());
+    }
+
+    #[test]
+    fn estimates_follow_the_embedding_rate() {
+        let cover = cover();
+        // RS and SPA are known to underestimate close to full embedding
+        let tolerance = 0.12;
+        for rate in [0.0, 0.5, 1.0] {
+            let stego = embed(&cover, rate);
+            let rs = rs_analysis(&stego);
+            let sample_pair = sample_pair_analysis(&stego);
+            assert!((rs - rate).abs() < tolerance, "RS {} at {}", rs, rate);
+            assert!(
+                (sample_pair - rate).abs() < tolerance,
+                "SPA {} at {}",
+                sample_pair,
+                rate
+            );
+        }
+    }
+
+    // chi-square gives the probability that there is a message at all rather than a rate
+    #[test]
+    fn chi_square_tells_covers_from_fully_embedded_images() {
+        let cover = cover();
+        assert!(chi_square_attack(&cover) < 0.05);
+        assert!(chi_square_attack(&embed(&cover, 1.0)) > 0.95);
+    }
+
+    #[test]
+    fn embedding_noise_flattens_the_finest_details() {
+        let cover = cover();
+        let kurtosis = [0.0, 0.5, 1.0].map(|rate| finest_kurtosis(&embed(&cover, rate)));
+        assert!(
+            kurtosis[0] > kurtosis[1] && kurtosis[1] > kurtosis[2],
+            "{:?}",
+            kurtosis
+        );
+        assert_eq!(wavelet_statistics(&cover).0.len(), (3 * 9 + 2 * 9) * 4);
+    }
+
+    #[test]
+    fn tiny_images_give_no_estimates() {
+        let mut img = PPMImage::new();
+        (img.width, img.height, img.max_val) = (1, 1, 255);
+        img.data.push(RGBColor::new(3, 4, 5));
+        let report = SteganalysisReport::from_ppm(&img);
+        assert_eq!(
+            (report.rs, report.sample_pair, report.wavelet),
+            (0.0, 0.0, 0.0)
+        );
+        assert!(report.features.is_empty());
+    }
+}
@@ -1,50 +1,196 @@
 use std::cmp::Ordering;
+use std::io::{Error, ErrorKind};
 
-use crate::color::RGBColor;
+use crate::algorithm::{self, StegoAlgorithm, StegoKey};
+use crate::color::{ColorSpace, RGBColor};
 use crate::image::PPMImage;
+use crate::pixel::{Pixel, Primitive, MAX_CHANNELS};
+use crate::progress::{Monitor, Stage};
 
-type Block<T> = [RGBColor<T>; 4];
+// 2x2 coefficients of a half-size sub-band, i.e. a 4x4 pixel area
+pub type Block<P> = [P; 4];
 const IH_INDEX: usize = 0;
 const IV_INDEX: usize = 1;
 const ID_INDEX: usize = 2;
 
+// The block replacement scheme on DWTImage behind the common algorithm interface
+#[derive(Debug, Default, Clone)]
+pub struct DWTStegano {
+    pub color_space: ColorSpace,
+    pub channels: ChannelSelection,
+    pub quantization: Quantization,
+    pub monitor: Monitor,
+}
+
+// Which channels of the cover (in the chosen color space) may carry the secret, and how much a
+// change in each of them costs when matching and placing blocks. Indexes are pixel channels, so
+// with the default selection the alpha channel of a layout that has one carries as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSelection {
+    pub carriers: [bool; MAX_CHANNELS],
+    pub weights: [f64; MAX_CHANNELS],
+}
+
+impl Default for ChannelSelection {
+    fn default() -> Self {
+        return ChannelSelection {
+            carriers: [true; MAX_CHANNELS],
+            weights: [1.0; MAX_CHANNELS],
+        };
+    }
+}
+
+impl ChannelSelection {
+    // e.g. `ChannelSelection::only(&[1, 2])` for Cb and Cr
+    pub fn only(channels: &[usize]) -> std::io::Result<ChannelSelection> {
+        let mut carriers = [false; MAX_CHANNELS];
+        for &channel in channels {
+            if channel >= MAX_CHANNELS {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Channel {} is not a pixel channel", channel),
+                ));
+            }
+            carriers[channel] = true;
+        }
+        let selection = ChannelSelection {
+            carriers,
+            ..ChannelSelection::default()
+        };
+        selection.validate(MAX_CHANNELS)?;
+        return Ok(selection);
+    }
+
+    // One weight per channel, missing ones stay 1
+    pub fn with_weights(self, weights: &[f64]) -> std::io::Result<ChannelSelection> {
+        if weights.len() > MAX_CHANNELS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Expected at most {} channel weights, got {}",
+                    MAX_CHANNELS,
+                    weights.len()
+                ),
+            ));
+        }
+        let mut result = self;
+        result.weights[..weights.len()].copy_from_slice(weights);
+        result.validate(MAX_CHANNELS)?;
+        return Ok(result);
+    }
+
+    fn carrier_indexes(&self, channels: usize) -> Vec<usize> {
+        return (0..channels).filter(|&c| self.carriers[c]).collect();
+    }
+
+    // The fields are public, so the entry points taking a selection check that some of the
+    // `channels` of the pixel layout it is used with carry, carriers past them are ignored
+    fn validate(&self, channels: usize) -> std::io::Result<()> {
+        if self.carrier_indexes(channels).is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "At least one channel has to carry the secret",
+            ));
+        }
+        // block errors are averaged with the weights of the `channels` first channels
+        let weights = &self.weights[..channels];
+        if let Some(weight) = weights
+            .iter()
+            .find(|weight| !(weight.is_finite() && **weight >= 0.0))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Channel weights have to be finite and not negative, got {}",
+                    weight
+                ),
+            ));
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "At least one channel weight has to be above 0",
+            ));
+        }
+        return Ok(());
+    }
+}
+
+// How the inverse transform turns coefficients back into pixel values. Replaced detail blocks
+// can push pixels outside 0..=max_val, clamping them changes the coefficients extraction reads,
+// so `projections` rounds of re-transforming the stego image and putting back the coefficients
+// the key refers to can be run, the other coefficients absorb the clamping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantization {
+    pub round: bool, // round to nearest instead of truncating
+    pub clamp: bool, // clamp to 0..=max_val instead of wrapping when written
+    pub projections: usize,
+}
+
+impl Default for Quantization {
+    fn default() -> Self {
+        return Quantization {
+            round: true,
+            clamp: true,
+            projections: 0,
+        };
+    }
+}
+
+impl Quantization {
+    pub fn projected(projections: usize) -> Quantization {
+        return Quantization {
+            projections,
+            ..Quantization::default()
+        };
+    }
+
+    fn quantize(&self, value: f64, max_val: usize) -> i32 {
+        let value = if self.round {
+            value.round()
+        } else {
+            value.trunc()
+        };
+        if self.clamp {
+            return value.clamp(0.0, max_val as f64) as i32;
+        }
+        return value as i32;
+    }
+}
+
+// Coefficients are i32 in the layout `P` whatever the sample type of the images they come from
+// and go back to, an image of layout `Q` goes with `DWTImage<Q::Of<i32>>`. Color spaces only
+// apply to the color channels of RGB layouts, gray layouts and alpha channels are transformed as
+// they are.
 #[derive(Debug)]
-pub struct DWTImage {
-    pub ll: Vec<RGBColor<i32>>, // approximation coefficients
-    pub lh: Vec<RGBColor<i32>>, // vertical details
-    pub hl: Vec<RGBColor<i32>>, // horizontal details
-    pub hh: Vec<RGBColor<i32>>, // diagonal details
+pub struct DWTImage<P = RGBColor<i32>> {
+    pub ll: Vec<P>, // approximation coefficients
+    pub lh: Vec<P>, // vertical details
+    pub hl: Vec<P>, // horizontal details
+    pub hh: Vec<P>, // diagonal details
     pub orig_width: usize,
     pub orig_height: usize,
+    pub max_val: usize,          // of the image the coefficients came from
+    pub color_space: ColorSpace, // space of the coefficients, inverse_dwt converts back to RGB
 }
 
 impl DWTImage {
-    pub fn new(
-        ll: Vec<RGBColor<i32>>,
-        lh: Vec<RGBColor<i32>>,
-        hl: Vec<RGBColor<i32>>,
-        hh: Vec<RGBColor<i32>>,
-        orig_width: usize,
-        orig_height: usize,
-    ) -> DWTImage {
-        DWTImage {
-            ll,
-            lh,
-            hl,
-            hh,
-            orig_width,
-            orig_height,
-        }
-    }
-
+    // key, replaced blocks and secret size, kept as they were before StegoKey
+    #[allow(clippy::type_complexity)]
     pub fn hide_image(
         orig_img_file_path: &str,
         secret_img_file_path: &str,
+        output_file_path: &str,
     ) -> std::io::Result<(Vec<usize>, Vec<(usize, usize)>, usize, usize)> {
-        let orginal_image = DWTImage::from_ppm(&PPMImage::from_file(&orig_img_file_path));
-        let message_image = DWTImage::from_ppm(&PPMImage::from_file(&secret_img_file_path));
-        let (watermarked_image, key, index_arr) = orginal_image.hide_message(&message_image);
-        watermarked_image.export_to_file("./images/watermarked.ppm")?;
+        let orginal_image = DWTImage::from_ppm(&PPMImage::from_file(orig_img_file_path)?);
+        let message_image = DWTImage::from_ppm(&PPMImage::from_file(secret_img_file_path)?);
+        let (watermarked_image, key, index_arr) = orginal_image.hide_message::<RGBColor<i32>>(
+            &message_image,
+            &ChannelSelection::default(),
+            &Quantization::default(),
+            &Monitor::default(),
+        )?;
+        watermarked_image.export_to_file(output_file_path)?;
 
         Ok((
             key,
@@ -62,47 +208,136 @@ impl DWTImage {
         orig_width: usize,
         orig_height: usize,
     ) -> std::io::Result<()> {
-        let ppm_img = PPMImage::from_file(&file_path);
-        let secret = DWTImage::from_ppm(&ppm_img); // DWT transform
+        let key = StegoKey {
+            width: orig_width,
+            height: orig_height,
+            block_indexes: key1,
+            replaced_blocks: key2,
+        };
+        let ppm_img = PPMImage::from_file(file_path)?;
+        DWTImage::extract_message(
+            &ppm_img,
+            &key,
+            ColorSpace::RGB,
+            &Quantization::default(),
+            &Monitor::default(),
+        )?
+        .export_to_file(output_file_path)?;
+        Ok(())
+    }
+}
+
+impl<P: Pixel<Sample = i32>> DWTImage<P> {
+    pub fn new(
+        ll: Vec<P>,
+        lh: Vec<P>,
+        hl: Vec<P>,
+        hh: Vec<P>,
+        orig_width: usize,
+        orig_height: usize,
+    ) -> DWTImage<P> {
+        DWTImage {
+            ll,
+            lh,
+            hl,
+            hh,
+            orig_width,
+            orig_height,
+            max_val: 255,
+            color_space: ColorSpace::RGB,
+        }
+    }
+
+    // Secret LL block i is its matched cover LL block plus the difference block stored in a
+    // detail sub-band, the secret detail sub-bands were never hidden and come back as zero
+    pub fn extract_message<Q: Pixel<Of<i32> = P>>(
+        img: &PPMImage<Q>,
+        key: &StegoKey,
+        color_space: ColorSpace,
+        quantization: &Quantization,
+        monitor: &Monitor,
+    ) -> std::io::Result<PPMImage<Q>> {
+        // the sub-bands of anything smaller are empty
+        if img.width < 2 || img.height < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Stego image needs to be at least 2x2 pixels",
+            ));
+        }
+        // every channel of the three detail sub-bands carrying, see `capacity`
+        algorithm::secret_pixels(key, (img.width / 4) * (img.height / 4) * 3 * 16)?;
+        monitor.step(Stage::Transform, 0, 1)?;
+        let stego = DWTImage::from_ppm_in(img, color_space); // DWT transform
+        monitor.step(Stage::Transform, 1, 1)?;
         let (ia, ih, iv, id) = (
-            DWTImage::blocking_extract_one(&secret.ll, secret.orig_width, secret.orig_height),
-            DWTImage::blocking_extract_one(&secret.lh, secret.orig_width, secret.orig_height),
-            DWTImage::blocking_extract_one(&secret.hl, secret.orig_width, secret.orig_height),
-            DWTImage::blocking_extract_one(&secret.hh, secret.orig_width, secret.orig_height),
+            DWTImage::blocking_extract_one(&stego.ll, stego.orig_width, stego.orig_height),
+            DWTImage::blocking_extract_one(&stego.lh, stego.orig_width, stego.orig_height),
+            DWTImage::blocking_extract_one(&stego.hl, stego.orig_width, stego.orig_height),
+            DWTImage::blocking_extract_one(&stego.hh, stego.orig_width, stego.orig_height),
         ); // Blocking
-        let mut bia = Vec::<Block<i32>>::new();
-        for i in 0..key1.len() {
-            bia.push(ia[key1[i]]);
-        }
-        let (mut dih, mut div, mut did) = (
-            Vec::<Block<i32>>::new(),
-            Vec::<Block<i32>>::new(),
-            Vec::<Block<i32>>::new(),
-        );
 
-        let max_width = (orig_width * orig_height) / 4;
-        for _ in 0..max_width {
-            dih.push([RGBColor::<i32>::default(); 4]);
-            div.push([RGBColor::<i32>::default(); 4]);
-            did.push([RGBColor::<i32>::default(); 4]);
+        let secret_blocks = (key.width / 4) * (key.height / 4);
+        if key.block_indexes.len() != secret_blocks
+            || key.replaced_blocks.len() != secret_blocks * P::CHANNELS
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Stego key does not match the secret dimensions",
+            ));
         }
+        let out_of_image = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Stego key refers to blocks outside of the image",
+            )
+        };
 
-        for i in 0..key2.len() {
-            if key2[i].0 == IH_INDEX {
-                dih[key2[i].1] = DWTImage::block_add(&bia[i], &ih[key2[i].1]);
-            } else if key2[i].0 == IV_INDEX {
-                div[key2[i].1] = DWTImage::block_add(&bia[i], &iv[key2[i].1]);
-            } else if key2[i].0 == ID_INDEX {
-                did[key2[i].1] = DWTImage::block_add(&bia[i], &id[key2[i].1]);
+        let mut sa = Vec::<Block<P>>::with_capacity(secret_blocks);
+        for i in 0..secret_blocks {
+            let mut difference: Block<P> = [P::default(); 4];
+            for c in 0..P::CHANNELS {
+                let (band, slot) = key.replaced_blocks[i * P::CHANNELS + c];
+                let details = match band {
+                    IH_INDEX => ih.get(slot / P::CHANNELS),
+                    IV_INDEX => iv.get(slot / P::CHANNELS),
+                    ID_INDEX => id.get(slot / P::CHANNELS),
+                    _ => None,
+                };
+                let unit =
+                    DWTImage::channel_unit(details.ok_or_else(out_of_image)?, slot % P::CHANNELS);
+                for j in 0..4 {
+                    *difference[j].channel_mut(c) = unit[j];
+                }
             }
+            let approximation = ia.get(key.block_indexes[i]).ok_or_else(out_of_image)?;
+            sa.push(DWTImage::block_add(approximation, &difference));
         }
 
-        let image = DWTImage::rearrange_blocks(&ia, &dih, &div, &did, orig_width, orig_height);
-        image.inverse_dwt().export_to_file(&output_file_path)?;
-        Ok(())
+        let zero = vec![[P::default(); 4]; secret_blocks];
+        let mut image = DWTImage::rearrange_blocks(
+            &sa,
+            &zero,
+            &zero,
+            &zero,
+            key.width,
+            key.height,
+            color_space,
+        );
+        image.max_val = stego.max_val;
+        monitor.step(Stage::InverseTransform, 0, 1)?;
+        let secret = image.inverse_dwt(quantization);
+        monitor.step(Stage::InverseTransform, 1, 1)?;
+        return Ok(secret);
     }
 
-    fn hide_message(&self, mess: &DWTImage) -> (PPMImage, Vec<usize>, Vec<(usize, usize)>) {
+    #[allow(clippy::type_complexity)]
+    fn hide_message<Q: Pixel<Of<i32> = P>>(
+        &self,
+        mess: &DWTImage<P>,
+        channels: &ChannelSelection,
+        quantization: &Quantization,
+        monitor: &Monitor,
+    ) -> std::io::Result<(PPMImage<Q>, Vec<usize>, Vec<(usize, usize)>)> {
         // blocking
         let (ia, mut ih, mut iv, mut id, sa) = (
             DWTImage::blocking_extract_one(&self.ll, self.orig_width, self.orig_height),
@@ -111,85 +346,200 @@ impl DWTImage {
             DWTImage::blocking_extract_one(&self.hh, self.orig_width, self.orig_height),
             DWTImage::blocking_extract_one(&mess.ll, mess.orig_width, mess.orig_height),
         );
-        let key1 = DWTImage::matching(&sa, &ia);
-        let mut bd = DWTImage::block_differences_computation(&sa, &ia, &key1);
-        let index_arr = DWTImage::block_replacement(&mut bd, &mut ih, &mut iv, &mut id);
-        let watermarked_image =
-            DWTImage::rearrange_blocks(&ia, &ih, &iv, &id, self.orig_width, self.orig_height);
+        let key1 = DWTImage::matching(&sa, &ia, &channels.weights, monitor)?;
+        let mut bd = DWTImage::block_differences_computation(&sa, &ia, &key1, monitor)?;
+        let index_arr =
+            DWTImage::block_replacement(&mut bd, &mut ih, &mut iv, &mut id, channels, monitor)?;
+        let mut watermarked_image = DWTImage::rearrange_blocks(
+            &ia,
+            &ih,
+            &iv,
+            &id,
+            self.orig_width,
+            self.orig_height,
+            self.color_space,
+        );
+        watermarked_image.max_val = self.max_val;
+
+        // one inverse transform plus one per projection round
+        let rounds = quantization.projections + 1;
+        monitor.step(Stage::InverseTransform, 0, rounds)?;
+        let mut stego = watermarked_image.inverse_dwt(quantization);
+        for round in 1..rounds {
+            monitor.step(Stage::InverseTransform, round, rounds)?;
+            match watermarked_image.project(&stego, &key1, &index_arr) {
+                Some(projected) => stego = projected.inverse_dwt(quantization),
+                None => break,
+            }
+        }
+        monitor.step(Stage::InverseTransform, rounds, rounds)?;
 
-        return (watermarked_image.inverse_dwt(), key1, index_arr);
+        return Ok((stego, key1, index_arr));
     }
 
-    fn inverse_dwt(&self) -> PPMImage {
-        let mut result_image = PPMImage::new();
-        let (x_left, x_right, y_left, y_right) = self.inverse_vertical_transform();
-        let secret_image = DWTImage::inverse_horizontal_transform(x_left, x_right, y_left, y_right);
-        let mut block_count = 0;
+    // Coefficients of `stego` with the ones the key refers to put back to their value in `self`,
+    // None once they all already match
+    fn project<Q: Pixel<Of<i32> = P>>(
+        &self,
+        stego: &PPMImage<Q>,
+        block_indexes: &[usize],
+        replaced_blocks: &[(usize, usize)],
+    ) -> Option<DWTImage<P>> {
+        let current = DWTImage::from_ppm_in(stego, self.color_space);
+        let (w, h) = (self.orig_width, self.orig_height);
+        let mut bands = [
+            DWTImage::blocking_extract_one(&current.ll, w, h),
+            DWTImage::blocking_extract_one(&current.lh, w, h),
+            DWTImage::blocking_extract_one(&current.hl, w, h),
+            DWTImage::blocking_extract_one(&current.hh, w, h),
+        ];
+        let targets = [
+            DWTImage::blocking_extract_one(&self.ll, w, h),
+            DWTImage::blocking_extract_one(&self.lh, w, h),
+            DWTImage::blocking_extract_one(&self.hl, w, h),
+            DWTImage::blocking_extract_one(&self.hh, w, h),
+        ];
+        let mut changed = false;
 
-        result_image.img_type = String::from("P6");
-        result_image.width = self.orig_width;
-        result_image.height = self.orig_height;
-        result_image.max_val = 255;
-        result_image.data = vec![RGBColor::new(0, 0, 0); self.orig_width * self.orig_height];
-
-        // Learn how to cast struct pls
-        for y in (0..self.orig_height).step_by(2) {
-            for x in (0..self.orig_width).step_by(2) {
-                result_image.data[(y + 0) * self.orig_width + (x + 0)] = RGBColor::new(
-                    secret_image[block_count][0].red as i32,
-                    secret_image[block_count][0].green as i32,
-                    secret_image[block_count][0].blue as i32,
-                );
-                // secret_image[block_count][0];
-                result_image.data[(y + 0) * self.orig_width + (x + 1)] = RGBColor::new(
-                    secret_image[block_count][1].red as i32,
-                    secret_image[block_count][1].green as i32,
-                    secret_image[block_count][1].blue as i32,
-                );
-                // secret_image[block_count][1];
-                result_image.data[(y + 1) * self.orig_width + (x + 0)] = RGBColor::new(
-                    secret_image[block_count][2].red as i32,
-                    secret_image[block_count][2].green as i32,
-                    secret_image[block_count][2].blue as i32,
-                );
-                // secret_image[block_count][2];
-                result_image.data[(y + 1) * self.orig_width + (x + 1)] = RGBColor::new(
-                    secret_image[block_count][3].red as i32,
-                    secret_image[block_count][3].green as i32,
-                    secret_image[block_count][3].blue as i32,
-                );
-                // secret_image[block_count][3];
-                block_count += 1;
+        for &index in block_indexes {
+            if bands[0][index] != targets[0][index] {
+                bands[0][index] = targets[0][index];
+                changed = true;
             }
         }
+        for &(band, slot) in replaced_blocks {
+            // detail sub-bands come after LL
+            let (band, index, carrier) = (band + 1, slot / P::CHANNELS, slot % P::CHANNELS);
+            for j in 0..4 {
+                let target = targets[band][index][j].channel(carrier);
+                let value = bands[band][index][j].channel_mut(carrier);
+                if *value != target {
+                    *value = target;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return None;
+        }
+
+        let [ia, ih, iv, id] = bands;
+        let mut image = DWTImage::rearrange_blocks(&ia, &ih, &iv, &id, w, h, self.color_space);
+        image.max_val = self.max_val;
+        return Some(image);
+    }
+
+    pub fn inverse_dwt<Q: Pixel<Of<i32> = P>>(&self, quantization: &Quantization) -> PPMImage<Q> {
+        let image = self.inverse_dwt_f64();
+        return PPMImage {
+            img_type: String::from(PPMImage::<Q>::default_img_type()),
+            width: image.width,
+            height: image.height,
+            max_val: self.max_val,
+            data: image
+                .data
+                .into_iter()
+                .map(|color| {
+                    color.convert(|value| {
+                        Primitive::from_i32(quantization.quantize(value, self.max_val))
+                    })
+                })
+                .collect(),
+        };
+    }
+
+    // The inverse transform back in RGB before quantization, e.g. to dump with `export_to_pfm`
+    // Pixel by pixel, each coefficient of the four sub-bands gives back one 2x2 block. An odd last
+    // row and column stay 0. `planar::PlanarDWT::inverse` is the same on planes.
+    pub fn inverse_dwt_f64(&self) -> PPMImage<P::Of<f64>> {
+        let (width, half_width) = (self.orig_width, self.orig_width / 2);
+        let mut result_image = PPMImage {
+            img_type: String::from(if P::COLOR_CHANNELS == 1 { "Pf" } else { "PF" }),
+            width,
+            height: self.orig_height,
+            max_val: self.max_val,
+            data: vec![P::Of::<f64>::default(); width * self.orig_height],
+        };
+        let half = |a: f64, b: f64| (a + b) / 2.0;
+        let sub = |a: f64, b: f64| a - b;
+
+        for i in 0..self.ll.len() {
+            let [ll, lh, hl, hh] = [self.ll[i], self.lh[i], self.hl[i], self.hh[i]]
+                .map(|color| -> P::Of<f64> { color.convert(|value| value as f64) });
+            // inverse vertical transform into the rows of the low and high halves
+            let low_top = ll.zip_channels(lh, half);
+            let low_bottom = ll.zip_channels(low_top, sub);
+            let high_top = hl.zip_channels(hh, half);
+            let high_bottom = hl.zip_channels(high_top, sub);
+            // inverse horizontal transform
+            let top_left = low_top.zip_channels(high_top, half);
+            let bottom_left = low_bottom.zip_channels(high_bottom, half);
+
+            let (x, y) = (i % half_width * 2, i / half_width * 2);
+            let data = &mut result_image.data;
+            data[(y + 0) * width + (x + 0)] = self.to_rgb(top_left);
+            data[(y + 0) * width + (x + 1)] = self.to_rgb(low_top.zip_channels(top_left, sub));
+            data[(y + 1) * width + (x + 0)] = self.to_rgb(bottom_left);
+            data[(y + 1) * width + (x + 1)] =
+                self.to_rgb(low_bottom.zip_channels(bottom_left, sub));
+        }
 
         return result_image;
     }
 
+    // LL, LH, HL and HH as half-size float images, the raw coefficients in `color_space`
+    pub fn sub_band_images(&self) -> [PPMImage<P::Of<f64>>; 4] {
+        return [&self.ll, &self.lh, &self.hl, &self.hh].map(|band| PPMImage {
+            img_type: String::from(if P::COLOR_CHANNELS == 1 { "Pf" } else { "PF" }),
+            width: self.orig_width / 2,
+            height: self.orig_height / 2,
+            max_val: self.max_val,
+            data: band
+                .iter()
+                .map(|color| color.convert(|value| value as f64))
+                .collect(),
+        });
+    }
+
+    fn to_rgb(&self, color: P::Of<f64>) -> P::Of<f64> {
+        let mut color = color;
+        if self.color_space != ColorSpace::RGB && P::COLOR_CHANNELS == 3 {
+            let scale = self.max_val.max(1) as f64;
+            let rgb = self.color_space.to_rgb(RGBColor::new(
+                color.channel(0) / scale,
+                color.channel(1) / scale,
+                color.channel(2) / scale,
+            ));
+            *color.channel_mut(0) = rgb.red * scale;
+            *color.channel_mut(1) = rgb.green * scale;
+            *color.channel_mut(2) = rgb.blue * scale;
+        }
+        return color;
+    }
+
     fn rearrange_blocks(
-        ia: &Vec<Block<i32>>,
-        ih: &Vec<Block<i32>>,
-        iv: &Vec<Block<i32>>,
-        id: &Vec<Block<i32>>,
+        ia: &[Block<P>],
+        ih: &[Block<P>],
+        iv: &[Block<P>],
+        id: &[Block<P>],
         width: usize,
         height: usize,
-    ) -> DWTImage {
+        color_space: ColorSpace,
+    ) -> DWTImage<P> {
         let (ll, lh, hl, hh) = (
-            DWTImage::rearrange_one_block(&ia, width / 2, height / 2),
-            DWTImage::rearrange_one_block(&ih, width / 2, height / 2),
-            DWTImage::rearrange_one_block(&iv, width / 2, height / 2),
-            DWTImage::rearrange_one_block(&id, width / 2, height / 2),
+            DWTImage::rearrange_one_block(ia, width / 2, height / 2),
+            DWTImage::rearrange_one_block(ih, width / 2, height / 2),
+            DWTImage::rearrange_one_block(iv, width / 2, height / 2),
+            DWTImage::rearrange_one_block(id, width / 2, height / 2),
         );
 
-        return DWTImage::new(ll, lh, hl, hh, width, height);
+        let mut image = DWTImage::new(ll, lh, hl, hh, width, height);
+        image.color_space = color_space;
+        return image;
     }
 
-    fn rearrange_one_block(
-        arr: &Vec<Block<i32>>,
-        width: usize,
-        height: usize,
-    ) -> Vec<RGBColor<i32>> {
-        let mut result: Vec<RGBColor<i32>> = vec![RGBColor::new(0, 0, 0); width * height];
+    fn rearrange_one_block(arr: &[Block<P>], width: usize, height: usize) -> Vec<P> {
+        let mut result: Vec<P> = vec![P::default(); width * height];
         let mut block_count = 0;
 
         for y in (0..height - 1).step_by(2) {
@@ -205,104 +555,151 @@ impl DWTImage {
         return result;
     }
 
-    fn block_replacement(
-        bd: &mut Vec<Block<i32>>,
-        ih: &mut Vec<Block<i32>>,
-        iv: &mut Vec<Block<i32>>,
-        id: &mut Vec<Block<i32>>,
-    ) -> Vec<(usize, usize)> {
+    // Every channel of a difference block goes separately into the carrier channel of a detail
+    // block where it costs the least, the cost being the error scaled by the carrier weight.
+    // Entries are (sub-band, block * channels + carrier), one per channel of every difference
+    // block, in channel order. A slot is never given twice, `capacity` makes sure there are enough.
+    pub fn block_replacement(
+        bd: &mut [Block<P>],
+        ih: &mut Vec<Block<P>>,
+        iv: &mut Vec<Block<P>>,
+        id: &mut Vec<Block<P>>,
+        channels: &ChannelSelection,
+        monitor: &Monitor,
+    ) -> std::io::Result<Vec<(usize, usize)>> {
+        channels.validate(P::CHANNELS)?;
+        let carriers = channels.carrier_indexes(P::CHANNELS);
         let mut index_arr = Vec::<(usize, usize)>::new();
+        // by sub-band then slot, a unit written over another one would be lost
+        let mut used =
+            [ih.len(), iv.len(), id.len()].map(|blocks| vec![false; blocks * P::CHANNELS]);
         for i in 0..bd.len() {
-            let (ih_index, iv_index, id_index) = (
-                DWTImage::find_most_fit_block_index(&bd[i], &ih),
-                DWTImage::find_most_fit_block_index(&bd[i], &iv),
-                DWTImage::find_most_fit_block_index(&bd[i], &id),
-            );
-            let elements = vec![ih[ih_index], iv[iv_index], id[id_index]];
-
-            let mut result = Vec::<(f64, usize)>::new();
-            result.push((
-                DWTImage::root_mean_square_error(&bd[i], &elements[0]),
-                ih_index,
-            ));
-            result.push((
-                DWTImage::root_mean_square_error(&bd[i], &elements[1]),
-                iv_index,
-            ));
-            result.push((
-                DWTImage::root_mean_square_error(&bd[i], &elements[2]),
-                id_index,
-            ));
-
-            quicksort::quicksort_by(&mut result, DWTImage::float_usize_tuple_compare);
-            match result[0].1 {
-                x if x == ih_index => {
-                    ih[ih_index] = bd[i];
-                    index_arr.push((IH_INDEX, ih_index));
-                }
-                x if x == iv_index => {
-                    iv[iv_index] = bd[i];
-                    index_arr.push((IV_INDEX, iv_index));
-                }
-                x if x == id_index => {
-                    id[id_index] = bd[i];
-                    index_arr.push((ID_INDEX, id_index));
+            monitor.step(Stage::BlockReplacement, i, bd.len())?;
+            for c in 0..P::CHANNELS {
+                let unit = DWTImage::channel_unit(&bd[i], c);
+                let mut result = Vec::<(f64, (usize, usize, usize))>::new();
+                for &carrier in &carriers {
+                    for (band, arr) in [(IH_INDEX, &*ih), (IV_INDEX, &*iv), (ID_INDEX, &*id)] {
+                        let Some(index) =
+                            DWTImage::find_most_fit_block_index(&unit, arr, carrier, &used[band])
+                        else {
+                            continue;
+                        };
+                        let error = Self::unit_root_mean_square_error(
+                            &unit,
+                            &DWTImage::channel_unit(&arr[index], carrier),
+                        );
+                        result.push((error * channels.weights[carrier], (band, index, carrier)));
+                    }
                 }
-                _ => {
-                    eprintln!("Unreachable, index = {}", result[0].1);
-                    panic!();
+
+                let Some(&(_, (band, index, carrier))) =
+                    result.iter().min_by(|e1, e2| e1.0.total_cmp(&e2.0))
+                else {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Every carrier slot of the detail sub-bands is taken",
+                    ));
+                };
+                used[band][index * P::CHANNELS + carrier] = true;
+                let target = match band {
+                    IH_INDEX => &mut ih[index],
+                    IV_INDEX => &mut iv[index],
+                    _ => &mut id[index],
+                };
+                for j in 0..4 {
+                    *target[j].channel_mut(carrier) = unit[j];
                 }
+                index_arr.push((band, index * P::CHANNELS + carrier));
             }
         }
-        return index_arr;
+        monitor.step(Stage::BlockReplacement, bd.len(), bd.len())?;
+        return Ok(index_arr);
     }
 
-    pub fn from_ppm(img: &PPMImage) -> DWTImage {
-        let (mut ll, mut lh, mut hl, mut hh) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
-        let (vec_low, vec_high) = DWTImage::horizontal_transform(img);
-        let range = if img.height % 2 == 0 {
-            (0..img.height).step_by(2)
-        } else {
-            (0..img.height - 1).step_by(2)
-        };
+    fn channel_unit(block: &Block<P>, channel: usize) -> [i32; 4] {
+        return block.map(|color| color.channel(channel));
+    }
 
-        // vertical transform
-        for y in range.clone() {
-            for x in 0..img.width / 2 {
-                ll.push(
-                    vec_low[(y + 1) * (img.width / 2) + x].add(&vec_low[y * (img.width / 2) + x]),
-                );
-                hl.push(
-                    vec_high[(y + 1) * (img.width / 2) + x].add(&vec_high[y * (img.width / 2) + x]),
-                );
-                lh.push(
-                    vec_low[y * (img.width / 2) + x].sub(&vec_low[(y + 1) * (img.width / 2) + x]),
-                );
-                hh.push(
-                    vec_high[y * (img.width / 2) + x].sub(&vec_high[(y + 1) * (img.width / 2) + x]),
-                );
-            }
+    // Pixels are converted to `color_space`, scaled back to 0..=max_val and rounded before the
+    // transform, `inverse_dwt` goes back to RGB at the same depth
+    pub fn from_ppm_in<Q: Pixel<Of<i32> = P>>(
+        img: &PPMImage<Q>,
+        color_space: ColorSpace,
+    ) -> DWTImage<P> {
+        if color_space == ColorSpace::RGB || P::COLOR_CHANNELS != 3 {
+            return DWTImage::transform(img);
+        }
+
+        let scale = img.max_val.max(1) as f64;
+        let mut converted = PPMImage {
+            img_type: img.img_type.clone(),
+            width: img.width,
+            height: img.height,
+            max_val: img.max_val,
+            data: Vec::with_capacity(img.data.len()),
+        };
+        for color in &img.data {
+            let rgb = RGBColor::new(
+                color.channel(0).to_f64() / scale,
+                color.channel(1).to_f64() / scale,
+                color.channel(2).to_f64() / scale,
+            );
+            let value = color_space.from_rgb(rgb);
+            let mut pixel: P = color.convert(|sample| sample.to_f64().round() as i32);
+            *pixel.channel_mut(0) = (value.red * scale).round() as i32;
+            *pixel.channel_mut(1) = (value.green * scale).round() as i32;
+            *pixel.channel_mut(2) = (value.blue * scale).round() as i32;
+            converted.data.push(pixel);
         }
 
-        return DWTImage::new(ll, lh, hl, hh, img.width, img.height);
+        let mut image = DWTImage::transform(&converted);
+        image.color_space = color_space;
+        return image;
     }
 
-    fn horizontal_transform(img: &PPMImage) -> (Vec<RGBColor<i32>>, Vec<RGBColor<i32>>) {
-        let mut vec_low = Vec::<RGBColor<i32>>::new();
-        let mut vec_high = Vec::<RGBColor<i32>>::new();
-        let range = if img.width % 2 == 0 {
-            (0..img.width).step_by(2)
-        } else {
-            (0..img.width - 1).step_by(2)
-        };
-        for y in 0..img.height {
-            for x in range.clone() {
-                vec_low.push(img.data[y * img.width + x].add(&img.data[y * img.width + (x + 1)]));
-                vec_high.push(img.data[y * img.width + x].sub(&img.data[y * img.width + (x + 1)]));
+    pub fn from_ppm<Q: Pixel<Of<i32> = P>>(img: &PPMImage<Q>) -> DWTImage<P> {
+        return DWTImage::transform(img);
+    }
+
+    // Any `Q` with as many channels as `P`, the caller makes sure they are the same layout.
+    // Pixel by pixel, each 2x2 block gives one coefficient of the four sub-bands, an odd last row
+    // and column are dropped. `planar::PlanarDWT::forward` is the same on planes.
+    fn transform<Q: Pixel>(img: &PPMImage<Q>) -> DWTImage<P> {
+        let (half_width, half_height) = (img.width / 2, img.height / 2);
+        let pixels = half_width * half_height;
+        let (mut ll, mut lh, mut hl, mut hh) = (
+            Vec::with_capacity(pixels),
+            Vec::with_capacity(pixels),
+            Vec::with_capacity(pixels),
+            Vec::with_capacity(pixels),
+        );
+        let pixel =
+            |x: usize, y: usize| -> P { img.data[y * img.width + x].convert(Primitive::to_i32) };
+        let add = |a: P, b: P| a.zip_channels(b, |x, y| x + y);
+        let sub = |a: P, b: P| a.zip_channels(b, |x, y| x - y);
+
+        for y in (0..half_height * 2).step_by(2) {
+            for x in (0..half_width * 2).step_by(2) {
+                // horizontal transform of the two rows of the block
+                let (top_left, top_right) = (pixel(x + 0, y + 0), pixel(x + 1, y + 0));
+                let (bottom_left, bottom_right) = (pixel(x + 0, y + 1), pixel(x + 1, y + 1));
+                let (low_top, high_top) = (add(top_left, top_right), sub(top_left, top_right));
+                let (low_bottom, high_bottom) = (
+                    add(bottom_left, bottom_right),
+                    sub(bottom_left, bottom_right),
+                );
+                // vertical transform
+                ll.push(add(low_top, low_bottom));
+                lh.push(sub(low_top, low_bottom));
+                hl.push(add(high_top, high_bottom));
+                hh.push(sub(high_top, high_bottom));
             }
         }
 
-        return (vec_low, vec_high);
+        let mut image = DWTImage::new(ll, lh, hl, hh, img.width, img.height);
+        image.max_val = img.max_val;
+        return image;
     }
 
     fn float_usize_tuple_compare(e1: &(f64, usize), e2: &(f64, usize)) -> Ordering {
@@ -315,52 +712,74 @@ impl DWTImage {
         }
     }
 
-    fn find_most_fit_block_index(bdc: &Block<i32>, arr: &Vec<Block<i32>>) -> usize {
+    // None once every block of `arr` has `channel` taken
+    fn find_most_fit_block_index(
+        unit: &[i32; 4],
+        arr: &[Block<P>],
+        channel: usize,
+        used: &[bool],
+    ) -> Option<usize> {
         let mut result = Vec::<(f64, usize)>::new();
-        for i in 0..arr.len() {
-            result.push((DWTImage::root_mean_square_error(&bdc, &arr[i]), i));
+        for (i, block) in arr.iter().enumerate() {
+            result.push((
+                Self::unit_root_mean_square_error(unit, &DWTImage::channel_unit(block, channel)),
+                i,
+            ));
         }
 
-        quicksort::quicksort_by(&mut result, DWTImage::float_usize_tuple_compare);
-        return result[0].1;
+        quicksort::quicksort_by(&mut result, Self::float_usize_tuple_compare);
+        return result
+            .into_iter()
+            .map(|(_, index)| index)
+            .find(|&index| !used[index * P::CHANNELS + channel]);
     }
 
-    #[allow(dead_code)]
-    fn block_differences_computation(
-        sa: &Vec<Block<i32>>,
-        ia: &Vec<Block<i32>>,
-        index_arr: &Vec<usize>,
-    ) -> Vec<Block<i32>> {
-        let mut result = Vec::<Block<i32>>::new();
+    pub fn block_differences_computation(
+        sa: &[Block<P>],
+        ia: &[Block<P>],
+        index_arr: &[usize],
+        monitor: &Monitor,
+    ) -> std::io::Result<Vec<Block<P>>> {
+        let mut result = Vec::<Block<P>>::new();
         for sa_index in 0..sa.len() {
+            monitor.step(Stage::BlockDifferences, sa_index, sa.len())?;
             result.push(DWTImage::block_sub(&sa[sa_index], &ia[index_arr[sa_index]]));
         }
-        return result;
+        monitor.step(Stage::BlockDifferences, sa.len(), sa.len())?;
+        return Ok(result);
     }
 
-    fn root_mean_square_error(vec1: &Block<i32>, vec2: &Block<i32>) -> f64 {
-        let mut result_color = RGBColor::<f64>::new(0.0, 0.0, 0.0);
-
+    fn unit_root_mean_square_error(unit1: &[i32; 4], unit2: &[i32; 4]) -> f64 {
+        let mut result = 0.0;
         for i in 0..4 {
-            result_color.red += ((vec2[i].red - vec1[i].red) as f64).powf(2.0);
-            result_color.green += ((vec2[i].green - vec1[i].green) as f64).powf(2.0);
-            result_color.blue += ((vec2[i].blue - vec1[i].blue) as f64).powf(2.0);
+            result += ((unit2[i] - unit1[i]) as f64).powf(2.0);
         }
+        return (result / 4.0).sqrt();
+    }
 
-        result_color.red = (result_color.red / 4.0).sqrt();
-        result_color.green = (result_color.green / 4.0).sqrt();
-        result_color.blue = (result_color.blue / 4.0).sqrt();
+    // Per channel RMSE, averaged with the channel weights
+    fn root_mean_square_error(
+        vec1: &Block<P>,
+        vec2: &Block<P>,
+        weights: &[f64; MAX_CHANNELS],
+    ) -> f64 {
+        let (mut result, mut weight_sum) = (0.0, 0.0);
+
+        for (c, weight) in weights.iter().enumerate().take(P::CHANNELS) {
+            let mut squares = 0.0;
+            for i in 0..4 {
+                squares += ((vec2[i].channel(c) - vec1[i].channel(c)) as f64).powf(2.0);
+            }
+            result += weight * (squares / 4.0).sqrt();
+            weight_sum += weight;
+        }
 
-        return (result_color.red + result_color.green + result_color.blue) / 3.0;
+        return result / weight_sum;
     }
 
-    fn blocking_extract_one(
-        mat: &Vec<RGBColor<i32>>,
-        orig_width: usize,
-        orig_height: usize,
-    ) -> Vec<Block<i32>> {
-        let mut result = Vec::<Block<i32>>::new();
-        let mut temp_arr: Block<i32> = [RGBColor::new(0, 0, 0); 4];
+    pub fn blocking_extract_one(mat: &[P], orig_width: usize, orig_height: usize) -> Vec<Block<P>> {
+        let mut result = Vec::<Block<P>>::new();
+        let mut temp_arr: Block<P> = [P::default(); 4];
 
         for y in (0..orig_height / 2 - 1).step_by(2) {
             for x in (0..orig_width / 2 - 1).step_by(2) {
@@ -375,150 +794,429 @@ impl DWTImage {
         return result;
     }
 
-    fn matching(sa: &Vec<Block<i32>>, ia: &Vec<Block<i32>>) -> Vec<usize> {
-        let mut result: Vec<Vec<(f64, usize)>> = vec![Vec::new(); sa.len()];
-        let mut index_arr = Vec::<usize>::new();
+    pub fn matching(
+        sa: &[Block<P>],
+        ia: &[Block<P>],
+        weights: &[f64; MAX_CHANNELS],
+        monitor: &Monitor,
+    ) -> std::io::Result<Vec<usize>> {
+        // only the best block so far is kept, a table of every pair would grow with the square of
+        // the image size
+        let mut best = vec![(f64::INFINITY, 0); sa.len()];
         for ia_index in 0..ia.len() {
+            monitor.step(Stage::Matching, ia_index, ia.len())?;
             for sa_index in 0..sa.len() {
-                result[sa_index].push((
-                    DWTImage::root_mean_square_error(&sa[sa_index], &ia[ia_index]),
-                    ia_index,
-                ));
+                let error = DWTImage::root_mean_square_error(&sa[sa_index], &ia[ia_index], weights);
+                if error < best[sa_index].0 {
+                    best[sa_index] = (error, ia_index);
+                }
             }
         }
 
-        for i in 0..sa.len() {
-            quicksort::quicksort_by(&mut result[i], DWTImage::float_usize_tuple_compare);
-            index_arr.push(result[i][0].1);
+        monitor.step(Stage::Matching, ia.len(), ia.len())?;
+        return Ok(best.into_iter().map(|(_, index)| index).collect());
+    }
+
+    fn block_sub(b1: &Block<P>, b2: &Block<P>) -> Block<P> {
+        let mut result: Block<P> = [P::default(); 4];
+        for i in 0..4 {
+            result[i] = b1[i].zip_channels(b2[i], |a, b| a - b);
         }
 
-        return index_arr;
+        return result;
     }
 
-    fn inverse_horizontal_transform(
-        x_left: Vec<RGBColor<f64>>,
-        x_right: Vec<RGBColor<f64>>,
-        y_left: Vec<RGBColor<f64>>,
-        y_right: Vec<RGBColor<f64>>,
-    ) -> Vec<Block<f64>> {
-        let mut result = Vec::<Block<f64>>::new();
-        let mut x: RGBColor<f64>;
-        let mut y: RGBColor<f64>;
-        let mut temp_block: Block<f64> = [RGBColor::new(0., 0., 0.); 4];
-
-        for i in 0..x_left.len() {
-            x = (x_left[i].add(&x_right[i])).div_by(2.0);
-            y = x_left[i].sub(&x);
-            temp_block[0] = x;
-            temp_block[1] = y;
-            x = (y_left[i].add(&y_right[i])).div_by(2.0);
-            y = y_left[i].sub(&x);
-            temp_block[2] = x;
-            temp_block[3] = y;
-            result.push(temp_block);
+    fn block_add(b1: &Block<P>, b2: &Block<P>) -> Block<P> {
+        let mut result: Block<P> = [P::default(); 4];
+        for i in 0..4 {
+            result[i] = b1[i].zip_channels(b2[i], |a, b| a + b);
         }
 
         return result;
     }
+}
 
-    fn inverse_vertical_transform(
-        &self,
-    ) -> (
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-    ) {
-        let mut x_left = Vec::<RGBColor<f64>>::new();
-        let mut x_right = Vec::<RGBColor<f64>>::new();
-        let mut y_left = Vec::<RGBColor<f64>>::new();
-        let mut y_right = Vec::<RGBColor<f64>>::new();
-        let mut x: RGBColor<f64>;
-        let mut y: RGBColor<f64>;
-
-        // FIXME: Learn how to cast from generic type to primitive type pls
-        let (self_ll, self_lh, self_hl, self_hh) = self.convert_to_f64();
+impl DWTStegano {
+    // `channels` is checked against the pixel layout on `embed`
+    pub fn new(color_space: ColorSpace, channels: ChannelSelection) -> DWTStegano {
+        return DWTStegano {
+            color_space,
+            channels,
+            quantization: Quantization::default(),
+            monitor: Monitor::default(),
+        };
+    }
 
-        for i in 0..self.ll.len() {
-            x = (self_ll[i].add(&self_lh[i])).div_by(2.0);
-            y = self_ll[i].sub(&x);
+    pub fn with_quantization(self, quantization: Quantization) -> DWTStegano {
+        return DWTStegano {
+            quantization,
+            ..self
+        };
+    }
 
-            x_left.push(x);
-            y_left.push(y);
+    // Progress of the stages and cancellation, see `progress::Monitor`
+    pub fn with_monitor(self, monitor: Monitor) -> DWTStegano {
+        return DWTStegano { monitor, ..self };
+    }
+}
 
-            x = (self_hl[i].add(&self_hh[i])).div_by(2.0);
-            y = self_hl[i].sub(&x);
+// Any sample type, the coefficients are i32 either way
+impl<P: Pixel> StegoAlgorithm<P> for DWTStegano {
+    fn embed(
+        &self,
+        cover: &PPMImage<P>,
+        secret: &PPMImage<P>,
+    ) -> std::io::Result<(PPMImage<P>, StegoKey)> {
+        self.channels.validate(P::CHANNELS)?;
+        if secret.width < 4 || secret.height < 4 || cover.width < 4 || cover.height < 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Cover and secret need to be at least 4x4 pixels",
+            ));
+        }
+        if secret.width * secret.height > self.capacity(cover) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Secret image is too large for this cover",
+            ));
+        }
 
-            x_right.push(x);
-            y_right.push(y);
+        self.monitor.step(Stage::Transform, 0, 2)?;
+        let original_image = DWTImage::<P::Of<i32>>::from_ppm_in(cover, self.color_space);
+        self.monitor.step(Stage::Transform, 1, 2)?;
+        let message_image = DWTImage::from_ppm_in(secret, self.color_space);
+        self.monitor.step(Stage::Transform, 2, 2)?;
+        let (mut stego, block_indexes, replaced_blocks) = original_image.hide_message(
+            &message_image,
+            &self.channels,
+            &self.quantization,
+            &self.monitor,
+        )?;
+        // the blocks only reach the whole 4x4 areas of the cover, the right and bottom edges past
+        // them keep the cover pixels instead of the zeros the inverse transform gives them
+        let (blocked_width, blocked_height) = (cover.width / 4 * 4, cover.height / 4 * 4);
+        for (i, color) in stego.data.iter_mut().enumerate() {
+            if i % cover.width >= blocked_width || i / cover.width >= blocked_height {
+                *color = cover.data[i];
+            }
         }
 
-        return (x_left, x_right, y_left, y_right);
+        return Ok((
+            stego,
+            StegoKey {
+                width: secret.width,
+                height: secret.height,
+                block_indexes,
+                replaced_blocks,
+            },
+        ));
     }
 
-    // This function shouldn't exist just like this entire code base
-    fn convert_to_f64(
-        &self,
-    ) -> (
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-        Vec<RGBColor<f64>>,
-    ) {
-        let (mut self_ll, mut self_lh, mut self_hl, mut self_hh) = (
-            Vec::<RGBColor<f64>>::new(),
-            Vec::<RGBColor<f64>>::new(),
-            Vec::<RGBColor<f64>>::new(),
-            Vec::<RGBColor<f64>>::new(),
+    fn extract(&self, stego: &PPMImage<P>, key: &StegoKey) -> std::io::Result<PPMImage<P>> {
+        return DWTImage::<P::Of<i32>>::extract_message(
+            stego,
+            key,
+            self.color_space,
+            &self.quantization,
+            &self.monitor,
         );
+    }
 
-        for i in 0..self.ll.len() {
-            self_ll.push(RGBColor::new(
-                self.ll[i].red as f64,
-                self.ll[i].green as f64,
-                self.ll[i].blue as f64,
-            ));
-            self_lh.push(RGBColor::new(
-                self.lh[i].red as f64,
-                self.lh[i].green as f64,
-                self.lh[i].blue as f64,
-            ));
-            self_hl.push(RGBColor::new(
-                self.hl[i].red as f64,
-                self.hl[i].green as f64,
-                self.hl[i].blue as f64,
-            ));
-            self_hh.push(RGBColor::new(
-                self.hh[i].red as f64,
-                self.hh[i].green as f64,
-                self.hh[i].blue as f64,
-            ));
+    // Each channel of a 4x4 secret block takes one carrier channel of one of the three detail
+    // blocks of a 4x4 cover block
+    fn capacity(&self, cover: &PPMImage<P>) -> usize {
+        let carriers = self.channels.carrier_indexes(P::CHANNELS).len();
+        return (cover.width / 4) * (cover.height / 4) * 3 * carriers / P::CHANNELS * 16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::CancellationToken;
+    use std::sync::{Arc, Mutex};
+
+    fn image(width: usize, height: usize) -> PPMImage {
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (width, height, 255);
+        for i in 0..width * height {
+            let i = i as i32;
+            img.data
+                .push(RGBColor::new(i * 7 % 256, i * 13 % 251, (i * i) % 241));
         }
+        return img;
+    }
 
-        return (self_ll, self_lh, self_hl, self_hh);
+    #[test]
+    fn inverse_undoes_forward() {
+        for (width, height) in [(2, 2), (8, 8), (16, 6), (10, 14)] {
+            let img = image(width, height);
+            let dwt = DWTImage::from_ppm(&img);
+            assert_eq!(dwt.ll.len(), width / 2 * height / 2);
+            let back = dwt.inverse_dwt::<RGBColor<i32>>(&Quantization::default());
+            assert_eq!((back.width, back.height), (width, height));
+            assert_eq!(back.data, img.data, "{}x{}", width, height);
+        }
     }
 
-    fn block_sub<T>(b1: &Block<T>, b2: &Block<T>) -> Block<T>
-    where
-        T: std::ops::Sub<Output = T> + Copy + Default,
-    {
-        let mut result: Block<T> = [RGBColor::<T>::default(); 4];
-        for i in 0..4 {
-            result[i] = b1[i].sub(&b2[i]);
+    #[test]
+    fn odd_sizes_lose_the_last_row_and_column() {
+        let img = image(9, 7);
+        let back = DWTImage::from_ppm(&img).inverse_dwt::<RGBColor<i32>>(&Quantization::default());
+        assert_eq!((back.width, back.height), (9, 7));
+        for y in 0..7 {
+            for x in 0..9 {
+                let expected = if x == 8 || y == 6 {
+                    RGBColor::default()
+                } else {
+                    img.data[y * 9 + x]
+                };
+                assert_eq!(back.data[y * 9 + x], expected, "({}, {})", x, y);
+            }
         }
+    }
 
-        return result;
+    #[test]
+    fn color_spaces_come_back_to_rgb() {
+        let img = image(8, 8);
+        // coefficients are rounded in the other space, which costs Lab the most
+        for (color_space, bound) in [
+            (ColorSpace::YCbCr601, 1),
+            (ColorSpace::YUV, 1),
+            (ColorSpace::HSV, 2),
+            (ColorSpace::Lab, 6),
+        ] {
+            let dwt = DWTImage::from_ppm_in(&img, color_space);
+            let back = dwt.inverse_dwt::<RGBColor<i32>>(&Quantization::default());
+            for (a, b) in back.data.iter().zip(&img.data) {
+                for c in 0..3 {
+                    let error = (a.channel(c) - b.channel(c)).abs();
+                    assert!(error <= bound, "{:?} off by {}", color_space, error);
+                }
+            }
+        }
     }
 
-    fn block_add<T>(b1: &Block<T>, b2: &Block<T>) -> Block<T>
-    where
-        T: std::ops::Add<Output = T> + Copy + Default,
-    {
-        let mut result: Block<T> = [RGBColor::<T>::default(); 4];
-        for i in 0..4 {
-            result[i] = b1[i].add(&b2[i]);
+    #[test]
+    fn color_spaces_keep_the_bit_depth() {
+        let mut img = image(8, 8);
+        img.max_val = 65535;
+        for color in &mut img.data {
+            *color = color.map_channels(|value| value * 257);
+        }
+        let dwt = DWTImage::from_ppm_in(&img, ColorSpace::YCbCr601);
+        assert_eq!(dwt.max_val, 65535);
+        let back = dwt.inverse_dwt::<RGBColor<i32>>(&Quantization::default());
+        assert_eq!(back.max_val, 65535);
+        for (a, b) in back.data.iter().zip(&img.data) {
+            for c in 0..3 {
+                let error = (a.channel(c) - b.channel(c)).abs();
+                assert!(error <= 1, "off by {}", error);
+            }
         }
 
-        return result;
+        let mut cover = image(32, 32);
+        cover.max_val = 65535;
+        for color in &mut cover.data {
+            *color = color.map_channels(|value| value * 257);
+        }
+        let stegano = DWTStegano::new(ColorSpace::YCbCr601, ChannelSelection::default());
+        let (stego, key) = stegano.embed(&cover, &image(8, 8)).unwrap();
+        assert_eq!(stego.max_val, 65535);
+        assert_eq!(stegano.extract(&stego, &key).unwrap().max_val, 65535);
+    }
+
+    #[test]
+    fn blocking_is_undone_by_rearranging() {
+        // sub-bands of a 16x12 image, 8x6 coefficients in 2x2 blocks
+        let band = image(8, 6).data;
+        let blocks = DWTImage::blocking_extract_one(&band, 16, 12);
+        assert_eq!(blocks.len(), 4 * 3);
+        assert_eq!(blocks[1], [band[2], band[3], band[10], band[11]]);
+        assert_eq!(DWTImage::rearrange_one_block(&blocks, 8, 6), band);
+        let back =
+            DWTImage::blocking_extract_one(&DWTImage::rearrange_one_block(&blocks, 8, 6), 16, 12);
+        assert_eq!(back, blocks);
+    }
+
+    #[test]
+    fn block_replacement_never_reuses_a_slot() {
+        // with flat detail sub-bands every block fits every unit as well as the others
+        let bd: Vec<Block<RGBColor<i32>>> =
+            (0..12).map(|i| [RGBColor::new(i, -i, 2 * i); 4]).collect();
+        let flat = vec![[RGBColor::default(); 4]; 4];
+        let (mut ih, mut iv, mut id) = (flat.clone(), flat.clone(), flat);
+        let channels = ChannelSelection::default();
+        let monitor = Monitor::default();
+        let slots = DWTImage::block_replacement(
+            &mut bd.clone(),
+            &mut ih,
+            &mut iv,
+            &mut id,
+            &channels,
+            &monitor,
+        )
+        .unwrap();
+
+        assert_eq!(slots.len(), 12 * 3);
+        let mut unique = slots.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), slots.len());
+        for (i, &(band, slot)) in slots.iter().enumerate() {
+            let arr = [&ih, &iv, &id][band];
+            let unit = DWTImage::channel_unit(&arr[slot / 3], slot % 3);
+            assert_eq!(unit, DWTImage::channel_unit(&bd[i / 3], i % 3));
+        }
+    }
+
+    #[test]
+    fn matching_picks_the_closest_block() {
+        let ia: Vec<Block<RGBColor<i32>>> =
+            (0..5).map(|i| [RGBColor::new(i * 10, 0, 0); 4]).collect();
+        let sa = vec![[RGBColor::new(31, 0, 0); 4], [RGBColor::new(-4, 0, 0); 4]];
+        let weights = ChannelSelection::default().weights;
+        let matched = DWTImage::matching(&sa, &ia, &weights, &Monitor::default()).unwrap();
+        assert_eq!(matched, vec![3, 0]);
+    }
+
+    #[test]
+    fn hidden_image_is_extracted() {
+        let (cover, secret) = (image(32, 32), image(8, 8));
+        // unclamped, the stego pixels are only rounded. That moves every coefficient by 2 at most,
+        // an extracted LL coefficient by 4 and its pixels by 1.
+        let stegano = DWTStegano::default().with_quantization(Quantization {
+            clamp: false,
+            ..Quantization::default()
+        });
+        let (stego, key) = stegano.embed(&cover, &secret).unwrap();
+        let extracted = stegano.extract(&stego, &key).unwrap();
+        assert_eq!((extracted.width, extracted.height), (8, 8));
+
+        // only the LL sub-band of the secret is hidden, each 2x2 area comes back as its mean
+        let mut expected = DWTImage::from_ppm(&secret);
+        for band in [&mut expected.lh, &mut expected.hl, &mut expected.hh] {
+            band.fill(RGBColor::default());
+        }
+        let expected = expected.inverse_dwt::<RGBColor<i32>>(&Quantization::default());
+        let error = extracted
+            .data
+            .iter()
+            .zip(&expected.data)
+            .map(|(a, b)| {
+                (0..3)
+                    .map(|c| (a.channel(c) - b.channel(c)).abs())
+                    .max()
+                    .unwrap()
+            })
+            .max()
+            .unwrap();
+        assert!(error <= 1, "off by {}", error);
+    }
+
+    #[test]
+    fn edges_past_the_blocks_keep_the_cover() {
+        let cover = image(18, 14);
+        let (stego, _) = DWTStegano::default().embed(&cover, &image(4, 4)).unwrap();
+        for (i, color) in stego.data.iter().enumerate() {
+            if i % 18 >= 16 || i / 18 >= 12 {
+                assert_eq!(*color, cover.data[i], "({}, {})", i % 18, i / 18);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_of_other_secrets_are_refused() {
+        let (cover, secret) = (image(32, 32), image(8, 8));
+        let (stego, mut key) = DWTStegano::default().embed(&cover, &secret).unwrap();
+        key.height = 12;
+        assert!(DWTStegano::default().extract(&stego, &key).is_err());
+        key.height = 8;
+        key.block_indexes[0] = 1 << 20;
+        assert!(DWTStegano::default().extract(&stego, &key).is_err());
+    }
+
+    #[test]
+    fn tiny_stego_images_are_refused() {
+        for (width, height) in [(1, 1), (1, 8), (8, 1), (0, 0)] {
+            let err = DWTStegano::default()
+                .extract(&image(width, height), &StegoKey::new(0, 0))
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn bad_selections_are_refused() {
+        assert!(ChannelSelection::only(&[]).is_err());
+        assert!(ChannelSelection::only(&[MAX_CHANNELS]).is_err());
+        assert!(ChannelSelection::only(&[1, 2]).is_ok());
+        let selection = ChannelSelection::default();
+        assert!(selection.with_weights(&[1.0; MAX_CHANNELS + 1]).is_err());
+        for weights in [[0.0; 4], [1.0, -1.0, 1.0, 1.0], [f64::NAN, 1.0, 1.0, 1.0]] {
+            assert!(selection.with_weights(&weights).is_err(), "{:?}", weights);
+        }
+        assert!(selection.with_weights(&[f64::INFINITY]).is_err());
+        assert!(selection.with_weights(&[0.0, 2.0]).is_ok());
+        // a gray image only averages with the first weight
+        let gray_only_zero = ChannelSelection {
+            weights: [0.0, 1.0, 1.0, 1.0],
+            ..selection
+        };
+        assert!(gray_only_zero.validate(1).is_err());
+
+        let (cover, secret) = (image(32, 32), image(8, 8));
+        let mut channels = ChannelSelection {
+            carriers: [false; MAX_CHANNELS],
+            ..ChannelSelection::default()
+        };
+        let err = DWTStegano::new(ColorSpace::RGB, channels)
+            .embed(&cover, &secret)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // only the alpha channel, which RGB pixels do not have
+        channels.carriers[3] = true;
+        assert!(DWTStegano::new(ColorSpace::RGB, channels)
+            .embed(&cover, &secret)
+            .is_err());
+
+        let flat = vec![[RGBColor::default(); 4]; 4];
+        let (mut ih, mut iv, mut id) = (flat.clone(), flat.clone(), flat.clone());
+        let result = DWTImage::block_replacement(
+            &mut flat.clone(),
+            &mut ih,
+            &mut iv,
+            &mut id,
+            &channels,
+            &Monitor::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn every_stage_reports_until_cancelled() {
+        let (cover, secret) = (image(32, 32), image(8, 8));
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let (sink, token) = (Arc::clone(&reports), CancellationToken::new());
+        let canceller = token.clone();
+        let monitor = Monitor::default()
+            .with_progress(move |stage, done, total| {
+                sink.lock().unwrap().push((stage, done, total));
+                if stage == Stage::BlockReplacement && done == 2 {
+                    canceller.cancel();
+                }
+            })
+            .with_cancellation(token.clone());
+        let stegano = DWTStegano::default().with_monitor(monitor);
+
+        let err = stegano.embed(&cover, &secret).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.last(), Some(&(Stage::BlockReplacement, 2, 4)));
+        for stage in [Stage::Transform, Stage::Matching, Stage::BlockDifferences] {
+            let last = reports.iter().rfind(|report| report.0 == stage).unwrap();
+            assert_eq!(last.1, last.2, "{} did not finish", stage.name());
+        }
     }
 }
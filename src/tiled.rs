@@ -0,0 +1,546 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::algorithm::{self, KeyReader, StegoAlgorithm, StegoKey};
+use crate::codecs;
+use crate::image::{HeaderReader, NetpbmHeader, PPMImage};
+use crate::limits::Limits;
+use crate::pixel::{self, Pixel};
+
+// Tiles are cut on this grid so that no 2x2 Haar pair, 4x4 DWT block or 8x8 DCT block straddles
+// two tiles. Every tile is then a cover of its own. Tiles never overlap: a pixel written by two
+// tiles would carry two strips of the secret and lose the first.
+const TILE_ALIGNMENT: usize = 8;
+// Rough peak per sample of a tile while it is embedded: the cover and stego tiles, the four
+// sub-bands and their block copies, and the f64 inverse transform
+const BYTES_PER_SAMPLE_IN_FLIGHT: usize = 48;
+// The secret is in memory whole next to the strip of it being embedded or extracted
+const SECRET_COPIES: usize = 2;
+const TILED_KEY_MAGIC: &[u8; 4] = b"IDWT";
+const TILED_KEY_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileGrid {
+    tile_width: usize,
+    tile_height: usize,
+}
+
+impl TileGrid {
+    // Sizes are rounded down to a multiple of 8 pixels, 8 at least
+    pub fn new(tile_width: usize, tile_height: usize) -> TileGrid {
+        let align = |size: usize| (size / TILE_ALIGNMENT * TILE_ALIGNMENT).max(TILE_ALIGNMENT);
+        return TileGrid {
+            tile_width: align(tile_width),
+            tile_height: align(tile_height),
+        };
+    }
+
+    // Square tiles keeping one tile of layout `P` in flight within what `budget_bytes` leaves
+    // next to a secret of `secret_pixels`
+    pub fn for_budget<P: Pixel>(
+        budget_bytes: usize,
+        secret_pixels: usize,
+    ) -> std::io::Result<TileGrid> {
+        let secret_bytes = secret_pixels.saturating_mul(SECRET_COPIES * std::mem::size_of::<P>());
+        let pixels =
+            budget_bytes.saturating_sub(secret_bytes) / (P::CHANNELS * BYTES_PER_SAMPLE_IN_FLIGHT);
+        if pixels < TILE_ALIGNMENT * TILE_ALIGNMENT {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "A budget of {} bytes leaves no room for a tile next to a secret of {} pixels",
+                    budget_bytes, secret_pixels
+                ),
+            ));
+        }
+        let side = (pixels as f64).sqrt() as usize;
+        return Ok(TileGrid::new(side, side));
+    }
+
+    pub fn tile_width(&self) -> usize {
+        return self.tile_width;
+    }
+
+    pub fn tile_height(&self) -> usize {
+        return self.tile_height;
+    }
+
+    // (x, y, width, height) of every tile of a `width` x `height` image in raster order, made as
+    // they are asked for. The last column and row of tiles also take the right and bottom edges
+    // narrower than 8 pixels, so every pixel is in exactly one tile. Images narrower or shorter
+    // than 8 pixels have no tiles.
+    pub fn tiles(
+        &self,
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = (usize, usize, usize, usize)> {
+        let (columns, rows) = (
+            tile_count(width, self.tile_width),
+            tile_count(height, self.tile_height),
+        );
+        let (tile_width, tile_height) = (self.tile_width, self.tile_height);
+        let extent = |index: usize, count: usize, tile: usize, size: usize| {
+            let start = index * tile;
+            return (
+                start,
+                if index + 1 == count {
+                    size - start
+                } else {
+                    tile
+                },
+            );
+        };
+        return (0..rows).flat_map(move |row| {
+            let (y, tile_rows) = extent(row, rows, tile_height, height);
+            (0..columns).map(move |column| {
+                let (x, tile_columns) = extent(column, columns, tile_width, width);
+                (x, y, tile_columns, tile_rows)
+            })
+        });
+    }
+
+    pub fn tile_count(&self, width: usize, height: usize) -> usize {
+        return tile_count(width, self.tile_width) * tile_count(height, self.tile_height);
+    }
+}
+
+// Tiles along a side of `size` pixels, a remainder narrower than the alignment joins the last one
+fn tile_count(size: usize, tile: usize) -> usize {
+    return (size / TILE_ALIGNMENT * TILE_ALIGNMENT).div_ceil(tile);
+}
+
+// The key of every tile and the grid they were made on. Key i goes with tile i of the grid, which
+// depends on the tile size as much as on the image size, so a strip can only be found again by
+// cutting the stego image on the same grid. Extraction takes the grid from here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TiledKey {
+    pub grid: TileGrid,
+    pub keys: Vec<StegoKey>,
+}
+
+impl TiledKey {
+    // The tile size followed by every key with its length in front
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(TILED_KEY_MAGIC);
+        buffer.push(TILED_KEY_VERSION);
+        buffer.extend_from_slice(&(self.grid.tile_width as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.grid.tile_height as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.keys.len() as u32).to_le_bytes());
+        for key in &self.keys {
+            let bytes = key.to_bytes();
+            buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&bytes);
+        }
+        return buffer;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<TiledKey> {
+        return TiledKey::from_bytes_with_limits(bytes, &Limits::default());
+    }
+
+    // `limits.max_key_bytes` goes for all the keys together
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &Limits) -> std::io::Result<TiledKey> {
+        limits.check_key(bytes.len())?;
+        let mut reader = KeyReader::new(bytes);
+        if reader.take(4)? != TILED_KEY_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a tiled stego key"));
+        }
+        let version = reader.take(1)?[0];
+        if version != TILED_KEY_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported tiled stego key version {}", version),
+            ));
+        }
+
+        let (tile_width, tile_height) = (reader.read_u32()?, reader.read_u32()?);
+        let grid = TileGrid::new(tile_width, tile_height);
+        if (grid.tile_width, grid.tile_height) != (tile_width, tile_height) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Tiles of {}x{} pixels are not aligned on {} pixels",
+                    tile_width, tile_height, TILE_ALIGNMENT
+                ),
+            ));
+        }
+        let count = reader.read_u32()?;
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            let length = reader.read_u32()?;
+            keys.push(StegoKey::from_bytes_with_limits(
+                reader.take(length)?,
+                limits,
+            )?);
+        }
+
+        if !reader.is_done() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Trailing bytes after tiled stego key",
+            ));
+        }
+        return Ok(TiledKey { grid, keys });
+    }
+}
+
+// Raw netpbm image (P5, P6 or P7, 8 or 16 bit) read and written in place through a memory map,
+// only the tiles asked for are ever decoded
+pub struct MappedImage<M = Mmap> {
+    map: M,
+    img_type: String,
+    header: NetpbmHeader,
+    offset: usize,
+}
+
+impl MappedImage {
+    pub fn open(file_path: &str) -> std::io::Result<MappedImage> {
+        let file = fs::File::open(file_path)?;
+        // the file must not be truncated by someone else while it is mapped
+        let map = unsafe { Mmap::map(&file)? };
+        return MappedImage::parse(map);
+    }
+}
+
+impl<M: Deref<Target = [u8]>> MappedImage<M> {
+    fn parse(map: M) -> std::io::Result<MappedImage<M>> {
+        let mut reader = HeaderReader::new(&map[..]);
+        let (img_type, header) = reader.netpbm_header()?;
+        if !matches!(img_type.as_str(), "P5" | "P6" | "P7") {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Tiled processing needs a raw P5, P6 or P7 image",
+            ));
+        }
+        reader.read_exact(&mut [0])?;
+        let offset = map.len() - reader.into_inner().len();
+
+        let raster_bytes = header
+            .width
+            .checked_mul(header.height)
+            .and_then(|pixels| pixels.checked_mul(header.depth() * header.sample_bytes()))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Netpbm image is too large"))?;
+        if map.len() - offset < raster_bytes {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Truncated netpbm image",
+            ));
+        }
+
+        return Ok(MappedImage {
+            map,
+            img_type,
+            header,
+            offset,
+        });
+    }
+
+    pub fn width(&self) -> usize {
+        return self.header.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.header.height;
+    }
+
+    pub fn max_val(&self) -> usize {
+        return self.header.max_val;
+    }
+
+    // The `width` x `height` area at (x, y) converted to `P` like `from_netpbm` does
+    pub fn read_tile<P: Pixel>(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> std::io::Result<PPMImage<P>> {
+        self.check_area(x, y, width, height)?;
+        let (depth, sample_bytes) = (self.header.depth(), self.header.sample_bytes());
+        let mut tile = PPMImage {
+            img_type: self.img_type.clone(),
+            width,
+            height,
+            max_val: pixel::stored_max_val::<P>(self.header.max_val),
+            data: Vec::with_capacity(width * height),
+        };
+
+        let mut samples = [0; 4];
+        for row in y..y + height {
+            let start = self.pixel_offset(x, row);
+            let bytes = &self.map[start..start + width * depth * sample_bytes];
+            for pixel in bytes.chunks_exact(depth * sample_bytes) {
+                for (c, sample) in pixel.chunks_exact(sample_bytes).enumerate() {
+                    samples[c] = match sample {
+                        [high, low] => u16::from_be_bytes([*high, *low]) as i32,
+                        _ => sample[0] as i32,
+                    };
+                }
+                tile.data.push(pixel::from_rgba(
+                    &self.header.to_rgba(&samples[..depth]),
+                    self.header.max_val,
+                ));
+            }
+        }
+        return Ok(tile);
+    }
+
+    fn check_area(&self, x: usize, y: usize, width: usize, height: usize) -> std::io::Result<()> {
+        if x + width > self.header.width || y + height > self.header.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Tile lies outside of the image",
+            ));
+        }
+        return Ok(());
+    }
+
+    fn pixel_offset(&self, x: usize, y: usize) -> usize {
+        let pixel_bytes = self.header.depth() * self.header.sample_bytes();
+        return self.offset + (y * self.header.width + x) * pixel_bytes;
+    }
+}
+
+impl MappedImage<MmapMut> {
+    fn open_mut(file: &fs::File) -> std::io::Result<MappedImage<MmapMut>> {
+        // the file is a temporary copy nobody else knows about
+        let map = unsafe { MmapMut::map_mut(file)? };
+        return MappedImage::parse(map);
+    }
+
+    // Writes `tile` at (x, y) in the layout and max_val of the file, samples are clamped
+    pub fn write_tile<P: Pixel>(
+        &mut self,
+        x: usize,
+        y: usize,
+        tile: &PPMImage<P>,
+    ) -> std::io::Result<()> {
+        self.check_area(x, y, tile.width, tile.height)?;
+        let (depth, sample_bytes) = (self.header.depth(), self.header.sample_bytes());
+        let max_val = self.header.max_val;
+
+        for (row, colors) in tile.data.chunks_exact(tile.width.max(1)).enumerate() {
+            let start = self.pixel_offset(x, y + row);
+            let bytes = &mut self.map[start..start + tile.width * depth * sample_bytes];
+            for (pixel, color) in bytes.chunks_exact_mut(depth * sample_bytes).zip(colors) {
+                let color = pixel::to_rgba(color, tile.max_val as i32);
+                let samples = self.header.samples(&color);
+                for (c, sample) in pixel.chunks_exact_mut(sample_bytes).enumerate() {
+                    let value = codecs::rescale(samples[c], tile.max_val, max_val) as u16;
+                    if sample_bytes == 2 {
+                        sample.copy_from_slice(&value.to_be_bytes());
+                    } else {
+                        sample[0] = value as u8;
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+// `algorithm::embed_in_pages` with the tiles of a raw netpbm cover as pages. The cover is copied
+// next to `output_file_path` and every tile is read from and written back to the copy through a
+// memory map, so only one tile and the secret are in memory at a time. The secret is counted
+// against `budget_bytes` and the tiles get the rest, see `TileGrid::for_budget`. The copy is
+// renamed over `output_file_path` once complete.
+pub fn hide_image_tiled<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    cover_file_path: &str,
+    secret_file_path: &str,
+    output_file_path: &str,
+    budget_bytes: usize,
+) -> std::io::Result<TiledKey> {
+    let limits = Limits {
+        max_bytes: budget_bytes / SECRET_COPIES,
+        ..Limits::default()
+    };
+    let secret = codecs::load_image_with_limits(secret_file_path, &limits)?;
+    let grid = TileGrid::for_budget::<P>(budget_bytes, secret.width * secret.height)?;
+    let output_path = Path::new(output_file_path);
+    let temporary_path = codecs::temporary_path(output_path)?;
+
+    fs::copy(cover_file_path, &temporary_path)?;
+    let result = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&temporary_path)
+        .and_then(|file| {
+            let mut stego = MappedImage::open_mut(&file)?;
+            let keys = embed_tiles(algorithm, &mut stego, &secret, &grid)?;
+            stego.map.flush()?;
+            drop(stego);
+            file.sync_all()?;
+            return Ok(TiledKey { grid, keys });
+        })
+        .and_then(|key| fs::rename(&temporary_path, output_path).map(|_| key));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    return result;
+}
+
+// Cuts the stego image on the grid of `key`, whatever the budget is now
+pub fn extract_image_tiled<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    stego_file_path: &str,
+    output_file_path: &str,
+    key: &TiledKey,
+) -> std::io::Result<()> {
+    let stego = MappedImage::open(stego_file_path)?;
+    codecs::save_image(
+        extract_tiles(algorithm, &stego, &key.keys, &key.grid)?,
+        output_file_path,
+    )?;
+
+    return Ok(());
+}
+
+fn embed_tiles<P: Pixel>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    stego: &mut MappedImage<MmapMut>,
+    secret: &PPMImage<P>,
+    grid: &TileGrid,
+) -> std::io::Result<Vec<StegoKey>> {
+    let mut keys = Vec::new();
+    let mut row = 0;
+
+    for (x, y, width, height) in grid.tiles(stego.width(), stego.height()) {
+        if row == secret.height {
+            break;
+        }
+        let tile = stego.read_tile(x, y, width, height)?;
+        let rows = algorithm::strip_rows(algorithm, &tile, secret, row);
+        if rows == 0 {
+            keys.push(StegoKey::new(secret.width, 0));
+            continue;
+        }
+
+        let (tile, key) = algorithm.embed(&tile, &algorithm::secret_strip(secret, row, rows))?;
+        stego.write_tile(x, y, &tile)?;
+        keys.push(key);
+        row += rows;
+    }
+
+    if row < secret.height {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Secret image is too large for this cover",
+        ));
+    }
+    return Ok(keys);
+}
+
+fn extract_tiles<P: Pixel, M: Deref<Target = [u8]>>(
+    algorithm: &dyn StegoAlgorithm<P>,
+    stego: &MappedImage<M>,
+    keys: &[StegoKey],
+    grid: &TileGrid,
+) -> std::io::Result<PPMImage<P>> {
+    if keys.is_empty() || keys.len() > grid.tile_count(stego.width(), stego.height()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Expected one stego key per tile holding the secret",
+        ));
+    }
+
+    let mut secret = PPMImage::new();
+    for ((x, y, width, height), key) in grid.tiles(stego.width(), stego.height()).zip(keys) {
+        if key.height > 0 {
+            let tile = stego.read_tile(x, y, width, height)?;
+            algorithm::stack_strip(&mut secret, algorithm.extract(&tile, key)?)?;
+        }
+    }
+    return Ok(secret);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::fixtures;
+    use crate::color::RGBColor;
+    use crate::lsb::LSBStegano;
+
+    fn mapped(img: &PPMImage) -> MappedImage<MmapMut> {
+        let mut bytes = Vec::new();
+        img.write_netpbm(&mut bytes).unwrap();
+        let mut map = MmapMut::map_anon(bytes.len()).unwrap();
+        map.copy_from_slice(&bytes);
+        return MappedImage::parse(map).unwrap();
+    }
+
+    #[test]
+    fn every_pixel_is_in_one_tile() {
+        let grid = TileGrid::new(32, 16);
+        for (width, height) in [(100, 37), (110, 8), (64, 16), (7, 30)] {
+            let mut covered = vec![0; width * height];
+            for (x, y, tile_width, tile_height) in grid.tiles(width, height) {
+                assert!(tile_width < 32 + 8 && tile_height < 16 + 8);
+                for row in y..y + tile_height {
+                    for column in x..x + tile_width {
+                        covered[row * width + column] += 1;
+                    }
+                }
+            }
+            let expected = if width < 8 { 0 } else { 1 };
+            assert!(
+                covered.iter().all(|&count| count == expected),
+                "{}x{}",
+                width,
+                height
+            );
+            assert_eq!(
+                grid.tiles(width, height).count(),
+                grid.tile_count(width, height)
+            );
+        }
+    }
+
+    #[test]
+    fn the_secret_is_counted_against_the_budget() {
+        let alone = TileGrid::for_budget::<RGBColor<i32>>(1 << 20, 0).unwrap();
+        let shared = TileGrid::for_budget::<RGBColor<i32>>(1 << 20, 32768).unwrap();
+        assert!(shared.tile_width() < alone.tile_width());
+        assert!(TileGrid::for_budget::<RGBColor<i32>>(1 << 20, 1 << 16).is_err());
+    }
+
+    #[test]
+    fn secrets_come_back_from_the_grid_of_their_key() {
+        let cover = fixtures::image::<RGBColor<i32>>(40, 24, 255);
+        let secret = fixtures::image::<RGBColor<i32>>(6, 10, 255);
+        let algorithm = LSBStegano::sequential(2).unwrap();
+        let grid = TileGrid::new(16, 16);
+        let mut stego = mapped(&cover);
+        let keys = embed_tiles(&algorithm, &mut stego, &secret, &grid).unwrap();
+        // a 16x16 tile carries 8 rows of the secret
+        assert_eq!(keys.len(), 2);
+
+        let key = TiledKey::from_bytes(&TiledKey { grid, keys }.to_bytes()).unwrap();
+        assert_eq!(key.grid, grid);
+        let extracted = extract_tiles(&algorithm, &stego, &key.keys, &key.grid).unwrap();
+        assert_eq!((extracted.width, extracted.height), (6, 10));
+        assert_eq!(extracted.data, secret.data);
+    }
+
+    #[test]
+    fn forged_tile_sizes_are_refused() {
+        let key = TiledKey {
+            grid: TileGrid::new(16, 8),
+            keys: vec![StegoKey::new(4, 4)],
+        };
+        let mut bytes = key.to_bytes();
+        bytes[5] = 12;
+        let err = TiledKey::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut bytes = key.to_bytes();
+        bytes.push(0);
+        assert!(TiledKey::from_bytes(&bytes).is_err());
+        assert!(TiledKey::from_bytes(&bytes[..bytes.len() - 2]).is_err());
+    }
+}
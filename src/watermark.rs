@@ -0,0 +1,485 @@
+use std::io::{Error, ErrorKind};
+
+use crate::color::RGBColor;
+use crate::image::PPMImage;
+use crate::pixel::Pixel;
+use crate::random::Fnv1a;
+use crate::stegano::{Block, DWTImage};
+
+// Every HH coefficient of a block carries one bit, one per channel
+const BITS_PER_BLOCK: usize = 12;
+// Direction of a move on each pixel of a quad, the signs of its HH coefficient
+const SIGNS: [i32; 4] = [1, -1, -1, 1];
+// Pixels covered by one side of a `Block` (2x2 coefficients of a half-size sub-band)
+const BLOCK_SIZE: usize = 4;
+
+// Blocks of one sub-band
+type Blocks = Vec<Block<RGBColor<i32>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkMode {
+    // Any modification of a block is reported
+    Fragile,
+    // LL is quantized by 2^n before hashing and HH bits are spread over 4 * 2^n wide bins,
+    // so small perturbations (noise, re-quantization) survive
+    SemiFragile(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FragileWatermark {
+    pub mode: WatermarkMode,
+    pub key: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TamperMap {
+    pub blocks_width: usize,
+    pub blocks_height: usize,
+    pub mismatches: Vec<u32>, // number of wrong HH bits per block, 0..=12
+    // number of HH bits per block no move within 0..=max_val could carry, only in images with a
+    // tiny max_val. They are left out of `mismatches`, so a block with all 12 is not verified.
+    pub unembeddable: Vec<u32>,
+}
+
+impl FragileWatermark {
+    pub fn new(mode: WatermarkMode, key: u64) -> FragileWatermark {
+        return FragileWatermark { mode, key };
+    }
+
+    // The LL sub-band is left untouched: every HH coefficient is moved in steps of 4 by
+    // adding (+k, -k, -k, +k) to its 2x2 pixel quad, which keeps LL, LH and HL constant.
+    // Quads with no room for a move, e.g. in flat black or white areas, first get their samples
+    // pulled `margin` away from 0 and max_val, the others are only moved. Quads that still have
+    // none (only when max_val is below twice the margin) are left as they are, `verify` counts
+    // them as unembeddable.
+    pub fn embed(&self, img: &PPMImage) -> std::io::Result<PPMImage> {
+        FragileWatermark::check_size(img)?;
+        let max_val = img.max_val as i32;
+        let margin = self.margin();
+        let mut result = PPMImage {
+            img_type: img.img_type.clone(),
+            width: img.width,
+            height: img.height,
+            max_val: img.max_val,
+            data: img.data.clone(),
+        };
+        let blocks_width = img.width / BLOCK_SIZE;
+        let blocks = blocks_width * (img.height / BLOCK_SIZE);
+        if max_val >= 2 * margin {
+            for i in 0..blocks {
+                for j in 0..4 {
+                    let indexes = FragileWatermark::quad_indexes(i, j, blocks_width, img.width);
+                    for c in 0..3 {
+                        let quad = indexes.map(|index| result.data[index].channel(c));
+                        if !self.has_room(quad, max_val) {
+                            for index in indexes {
+                                let sample = result.data[index].channel_mut(c);
+                                *sample = (*sample).clamp(margin, max_val - margin);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let dwt = DWTImage::from_ppm(&result);
+        let (ll, lh, hl, hh) = self.blocks(&dwt);
+
+        for i in 0..ll.len() {
+            let bits = self.block_hash(i, &ll[i], &lh[i], &hl[i], &hh[i]);
+            for (j, coefficients) in hh[i].iter().enumerate() {
+                let indexes = FragileWatermark::quad_indexes(i, j, blocks_width, img.width);
+                for c in 0..3 {
+                    let quad = indexes.map(|index| result.data[index].channel(c));
+                    let coefficient = coefficients.channel(c);
+                    let bit = bit_at(bits, j * 3 + c);
+                    let moved = self
+                        .embed_bit(quad, coefficient, bit, max_val)
+                        .unwrap_or(quad);
+                    for q in 0..4 {
+                        *result.data[indexes[q]].channel_mut(c) = moved[q];
+                    }
+                }
+            }
+        }
+
+        return Ok(result);
+    }
+
+    // When `embed` could pull the samples away from 0 and max_val every bit was embedded, so
+    // every wrong bit is tampering, flat black or white overpaints included. Otherwise a moved quad can
+    // be moved back and forth along the same (+k, -k, -k, +k) line, so the moves `embed` had are
+    // the ones the quad has now, and a wrong bit none of them could carry was never embedded.
+    pub fn verify(&self, img: &PPMImage) -> std::io::Result<TamperMap> {
+        FragileWatermark::check_size(img)?;
+        let dwt = DWTImage::from_ppm(img);
+        let (ll, lh, hl, hh) = self.blocks(&dwt);
+        let blocks_width = img.width / BLOCK_SIZE;
+        let max_val = img.max_val as i32;
+        let clamped = max_val >= 2 * self.margin();
+        let mut mismatches = Vec::<u32>::with_capacity(ll.len());
+        let mut unembeddable = Vec::<u32>::with_capacity(ll.len());
+
+        for i in 0..ll.len() {
+            let bits = self.block_hash(i, &ll[i], &lh[i], &hl[i], &hh[i]);
+            let (mut wrong, mut skipped) = (0, 0);
+            for (j, coefficients) in hh[i].iter().enumerate() {
+                let indexes = FragileWatermark::quad_indexes(i, j, blocks_width, img.width);
+                for c in 0..3 {
+                    let quad = indexes.map(|index| img.data[index].channel(c));
+                    let coefficient = coefficients.channel(c);
+                    let bit = bit_at(bits, j * 3 + c);
+                    if self.extract_bit(coefficient) == bit {
+                        continue;
+                    }
+                    if clamped || self.embed_bit(quad, coefficient, bit, max_val).is_some() {
+                        wrong += 1;
+                    } else {
+                        skipped += 1;
+                    }
+                }
+            }
+            mismatches.push(wrong);
+            unembeddable.push(skipped);
+        }
+
+        return Ok(TamperMap {
+            blocks_width,
+            blocks_height: img.height / BLOCK_SIZE,
+            mismatches,
+            unembeddable,
+        });
+    }
+
+    // the sub-bands of anything smaller are empty
+    fn check_size(img: &PPMImage) -> std::io::Result<()> {
+        if img.width < 2 || img.height < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Watermarked image needs to be at least 2x2 pixels",
+            ));
+        }
+        return Ok(());
+    }
+
+    fn blocks(&self, dwt: &DWTImage) -> (Blocks, Blocks, Blocks, Blocks) {
+        return (
+            DWTImage::blocking_extract_one(&dwt.ll, dwt.orig_width, dwt.orig_height),
+            DWTImage::blocking_extract_one(&dwt.lh, dwt.orig_width, dwt.orig_height),
+            DWTImage::blocking_extract_one(&dwt.hl, dwt.orig_width, dwt.orig_height),
+            DWTImage::blocking_extract_one(&dwt.hh, dwt.orig_width, dwt.orig_height),
+        );
+    }
+
+    // Pixels of the 2x2 quad behind coefficient `j` of block `i`
+    fn quad_indexes(i: usize, j: usize, blocks_width: usize, width: usize) -> [usize; 4] {
+        let (block_x, block_y) = (i % blocks_width, i / blocks_width);
+        let x = (2 * block_x + j % 2) * 2;
+        let y = (2 * block_y + j / 2) * 2;
+        return [
+            (y + 0) * width + (x + 0),
+            (y + 0) * width + (x + 1),
+            (y + 1) * width + (x + 0),
+            (y + 1) * width + (x + 1),
+        ];
+    }
+
+    fn bin_width(&self) -> i32 {
+        return match self.mode {
+            WatermarkMode::Fragile => 4,
+            WatermarkMode::SemiFragile(n) => 4 << n,
+        };
+    }
+
+    // The bit flips every 2^n moves, a quad needs 2^n + 1 of them to always reach both
+    fn margin(&self) -> i32 {
+        return (self.bin_width() / 8).max(1);
+    }
+
+    fn extract_bit(&self, coefficient: i32) -> bool {
+        return coefficient.div_euclid(self.bin_width()) & 1 == 1;
+    }
+
+    // Moves one channel of a pixel quad by (+k, -k, -k, +k), i.e. its HH coefficient by 4k,
+    // into a bin carrying `bit`. Bin centers are preferred for robustness, then the smallest
+    // change, among the moves keeping the quad in 0..=max_val. The bit flips every 2^n steps and
+    // up to 2^(n+1) + 1 steps are tried on each side, so when no move is found none exists.
+    fn embed_bit(
+        &self,
+        quad: [i32; 4],
+        coefficient: i32,
+        bit: bool,
+        max_val: i32,
+    ) -> Option<[i32; 4]> {
+        let width = self.bin_width();
+        let (lowest, highest) = FragileWatermark::moves(quad, width / 2 + 1, max_val);
+
+        let mut best: Option<(i32, i32, i32)> = None;
+        for k in lowest..=highest {
+            let moved = coefficient + 4 * k;
+            if self.extract_bit(moved) == bit {
+                let distance = (moved.rem_euclid(width) - width / 2).abs();
+                let candidate = (distance, k.abs(), k);
+                if best.is_none_or(|best| candidate < best) {
+                    best = Some(candidate);
+                }
+            }
+        }
+
+        return best.map(|(_, _, k)| [0, 1, 2, 3].map(|q| quad[q] + SIGNS[q] * k));
+    }
+
+    // The bit flips every 2^n moves, 2^n + 1 of them reach both values from any coefficient
+    fn has_room(&self, quad: [i32; 4], max_val: i32) -> bool {
+        let (lowest, highest) = FragileWatermark::moves(quad, max_val, max_val);
+        return highest - lowest >= self.bin_width() / 4;
+    }
+
+    // Lowest and highest k of the (+k, -k, -k, +k) moves keeping every pixel of the quad in
+    // 0..=max_val, at most `max_steps` away from 0
+    fn moves(quad: [i32; 4], max_steps: i32, max_val: i32) -> (i32, i32) {
+        let (mut lowest, mut highest) = (-max_steps, max_steps);
+        for q in 0..4 {
+            let (down, up) = (quad[q], max_val - quad[q]);
+            let (below, above) = if SIGNS[q] > 0 { (down, up) } else { (up, down) };
+            lowest = lowest.max(-below);
+            highest = highest.min(above);
+        }
+        return (lowest, highest);
+    }
+
+    fn block_hash(
+        &self,
+        index: usize,
+        ll: &Block<RGBColor<i32>>,
+        lh: &Block<RGBColor<i32>>,
+        hl: &Block<RGBColor<i32>>,
+        hh: &Block<RGBColor<i32>>,
+    ) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write_u64(self.key);
+        hasher.write_u64(index as u64);
+
+        match self.mode {
+            WatermarkMode::Fragile => {
+                // LH, HL and the low bits of HH are not changed by embedding either,
+                // hashing them too catches changes that keep the block average
+                for block in [ll, lh, hl] {
+                    for color in block {
+                        hasher.write_color(color);
+                    }
+                }
+                for color in hh {
+                    hasher.write_color(&RGBColor::new(
+                        color.red.rem_euclid(4),
+                        color.green.rem_euclid(4),
+                        color.blue.rem_euclid(4),
+                    ));
+                }
+            }
+            WatermarkMode::SemiFragile(n) => {
+                for color in ll {
+                    hasher.write_color(&RGBColor::new(
+                        color.red >> n,
+                        color.green >> n,
+                        color.blue >> n,
+                    ));
+                }
+            }
+        }
+
+        return hasher.finish();
+    }
+}
+
+impl TamperMap {
+    pub fn is_tampered(&self) -> bool {
+        return self.mismatches.iter().any(|&count| count > 0);
+    }
+
+    // (x, y) of every tampered block, in block coordinates
+    pub fn tampered_blocks(&self) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        for i in 0..self.mismatches.len() {
+            if self.mismatches[i] > 0 {
+                result.push((i % self.blocks_width, i / self.blocks_width));
+            }
+        }
+        return result;
+    }
+
+    // One 4x4 pixel square per block, black when intact, from dark to bright red
+    // with the number of mismatching bits
+    pub fn to_heatmap(&self) -> PPMImage {
+        let (width, height) = (
+            self.blocks_width * BLOCK_SIZE,
+            self.blocks_height * BLOCK_SIZE,
+        );
+        let mut heatmap = PPMImage {
+            img_type: String::from("P6"),
+            width,
+            height,
+            max_val: 255,
+            data: vec![RGBColor::new(0, 0, 0); width * height],
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let count = self.mismatches[(y / BLOCK_SIZE) * self.blocks_width + x / BLOCK_SIZE];
+                if count > 0 {
+                    let intensity = 64 + (191 * count as i32) / BITS_PER_BLOCK as i32;
+                    heatmap.data[y * width + x] = RGBColor::new(intensity, 0, 0);
+                }
+            }
+        }
+
+        return heatmap;
+    }
+
+    pub fn export_to_file(&self, file_path: &str) -> std::io::Result<()> {
+        return self.to_heatmap().export_to_file(file_path);
+    }
+}
+
+fn bit_at(bits: u64, index: usize) -> bool {
+    return (bits >> index) & 1 == 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SplitMix64;
+
+    // Noise on the left half, black then white on the right half
+    fn image() -> PPMImage {
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (32, 32, 255);
+        let mut random = SplitMix64::new(7);
+        for y in 0..32 {
+            for x in 0..32 {
+                let value = |random: &mut SplitMix64| random.below(256) as i32;
+                img.data.push(match (x < 16, y < 16) {
+                    (true, _) => {
+                        RGBColor::new(value(&mut random), value(&mut random), value(&mut random))
+                    }
+                    (false, true) => RGBColor::new(0, 0, 0),
+                    (false, false) => RGBColor::new(255, 255, 255),
+                });
+            }
+        }
+        return img;
+    }
+
+    #[test]
+    fn untouched_images_verify_clean() {
+        for mode in [WatermarkMode::Fragile, WatermarkMode::SemiFragile(2)] {
+            let watermark = FragileWatermark::new(mode, 42);
+            let map = watermark
+                .verify(&watermark.embed(&image()).unwrap())
+                .unwrap();
+            assert!(
+                !map.is_tampered(),
+                "{:?}: {:?}",
+                mode,
+                map.tampered_blocks()
+            );
+            // the margin leaves room for a move even in the black and white blocks
+            assert!(
+                map.unembeddable.iter().all(|&skipped| skipped == 0),
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn unembeddable_bits_are_not_tampering() {
+        // max_val is below twice the margin of 4 * 2^3 wide bins
+        let mut img = image();
+        img.max_val = 3;
+        for color in &mut img.data {
+            *color = color.map_channels(|value| value % 4);
+        }
+        let watermark = FragileWatermark::new(WatermarkMode::SemiFragile(3), 42);
+        let map = watermark.verify(&watermark.embed(&img).unwrap()).unwrap();
+        assert!(!map.is_tampered(), "{:?}", map.tampered_blocks());
+        assert!(map.unembeddable.iter().any(|&skipped| skipped > 0));
+    }
+
+    #[test]
+    fn flat_overpaints_are_tampering() {
+        for mode in [WatermarkMode::Fragile, WatermarkMode::SemiFragile(2)] {
+            let watermark = FragileWatermark::new(mode, 42);
+            for value in [0, 255] {
+                let mut stego = watermark.embed(&image()).unwrap();
+                for y in 0..16 {
+                    for x in 0..16 {
+                        stego.data[y * 32 + x] = RGBColor::new(value, value, value);
+                    }
+                }
+                let map = watermark.verify(&stego).unwrap();
+                let tampered = map.tampered_blocks();
+                assert!(!tampered.is_empty(), "{:?}: painted {}", mode, value);
+                assert!(
+                    tampered.iter().all(|&(x, y)| x < 4 && y < 4),
+                    "{:?}: {:?}",
+                    mode,
+                    tampered
+                );
+                assert!(map.unembeddable.iter().all(|&skipped| skipped == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn local_edits_are_found_where_they_are() {
+        let watermark = FragileWatermark::new(WatermarkMode::Fragile, 42);
+        for (x, y) in [(13, 9), (1, 30), (20, 4), (27, 21)] {
+            let mut stego = watermark.embed(&image()).unwrap();
+            let pixel = &mut stego.data[y * 32 + x];
+            pixel.red = if pixel.red > 128 {
+                pixel.red - 60
+            } else {
+                pixel.red + 60
+            };
+            let map = watermark.verify(&stego).unwrap();
+            assert_eq!(map.tampered_blocks(), vec![(x / 4, y / 4)]);
+        }
+    }
+
+    #[test]
+    fn fragile_marks_only_move_the_quads_they_need() {
+        // a black and white checkerboard with a 2 pixel edge past the last block, every quad of
+        // which has room for a move in one direction
+        let mut img = PPMImage::new();
+        img.img_type = String::from("P6");
+        (img.width, img.height, img.max_val) = (34, 34, 255);
+        for y in 0..34 {
+            for x in 0..34 {
+                let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+                img.data.push(RGBColor::new(value, value, value));
+            }
+        }
+        let watermark = FragileWatermark::new(WatermarkMode::Fragile, 42);
+        let stego = watermark.embed(&img).unwrap();
+        assert!(!watermark.verify(&stego).unwrap().is_tampered());
+
+        let kept = |x: usize, y: usize| stego.data[y * 34 + x] == img.data[y * 34 + x];
+        assert!((0..34).all(|i| (32..34).all(|edge| kept(edge, i) && kept(i, edge))));
+        // quads whose bits were already right are not pulled away from 0 and 255
+        assert!((0..32).any(|y| (0..32).any(|x| kept(x, y) && img.data[y * 34 + x].red == 0)));
+    }
+
+    #[test]
+    fn images_under_2_pixels_are_refused() {
+        let watermark = FragileWatermark::new(WatermarkMode::Fragile, 42);
+        for (width, height) in [(1, 5), (5, 1), (0, 0)] {
+            let mut img = PPMImage::new();
+            (img.width, img.height, img.max_val) = (width, height, 255);
+            img.data = vec![RGBColor::new(1, 2, 3); width * height];
+            let err = watermark.verify(&img).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(watermark.embed(&img).is_err());
+        }
+    }
+}